@@ -0,0 +1,31 @@
+;; Example WasmNode module, playing each note alongside its fifth.
+;;
+;; A module exports `memory`, `process(instruction, channel, note, velocity) -> count` and `output() -> ptr`,
+;; where `ptr` addresses `count` 8 byte records of [instruction, channel, note, velocity, delay (f32, LE)].
+;; It may also export `init(ptr, len)`, receiving the node's params as JSON written via `alloc(len) -> ptr`.
+(module
+  (import "mechsync" "log" (func $log (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "fifths ready")
+
+  (func (export "init") (param $ptr i32) (param $len i32)
+    (call $log (i32.const 0) (i32.const 12)))
+
+  (func (export "output") (result i32)
+    (i32.const 64))
+
+  (func $write (param $offset i32) (param $instruction i32) (param $channel i32) (param $note i32) (param $velocity i32)
+    (i32.store8 offset=0 (local.get $offset) (local.get $instruction))
+    (i32.store8 offset=1 (local.get $offset) (local.get $channel))
+    (i32.store8 offset=2 (local.get $offset) (local.get $note))
+    (i32.store8 offset=3 (local.get $offset) (local.get $velocity))
+    (f32.store offset=4 (local.get $offset) (f32.const 0)))
+
+  (func (export "process") (param $instruction i32) (param $channel i32) (param $note i32) (param $velocity i32) (result i32)
+    (call $write (i32.const 64) (local.get $instruction) (local.get $channel) (local.get $note) (local.get $velocity))
+    ;; drop the fifth if it would leave the MIDI note range
+    (if (i32.gt_u (i32.add (local.get $note) (i32.const 7)) (i32.const 127))
+      (then (return (i32.const 1))))
+    (call $write (i32.const 72)
+      (local.get $instruction) (local.get $channel) (i32.add (local.get $note) (i32.const 7)) (local.get $velocity))
+    (i32.const 2)))
@@ -0,0 +1,16 @@
+import mechsync
+from mechsync import Message
+
+
+def call(instruction: int, channel: int, note: int, velocity: int):
+    message = Message(instruction, channel, note, velocity)
+
+    # drop everything other than note-ons
+    if not message.is_note_on:
+        return None
+
+    # play a major triad, releasing each note half a second later
+    chord = [note, note + 4, note + 7]
+    mechsync.debug(f"chord {chord} at {mechsync.time():.3f}s")
+    return [Message.note_on(channel, n, velocity) for n in chord] + \
+        [Message.note_off(channel, n, delay=0.5) for n in chord]
@@ -0,0 +1,11 @@
+class Transposer:
+    def __init__(self, params: dict):
+        self.semitones = params.get("semitones", 0)
+        self.count = 0
+
+    def call(self, instruction: int, channel: int, note: int, velocity: int) -> (int, int, int, int, float):
+        self.count += 1
+        return (instruction, channel, min(127, max(0, note + self.semitones)), velocity, 0.0)
+
+    def shutdown(self):
+        print(f"Transposer handled {self.count} messages")
@@ -0,0 +1,49 @@
+use std::sync::{Arc, Weak};
+use may::sync::RwLock;
+use serde::Deserialize;
+use mechsync::config::{register, Config, ConfigError, NodeFactory};
+use mechsync::data::MidiData;
+use mechsync::node::{Node, OptNode};
+
+// shifts every note by a fixed number of semitones, e.g.
+// - name: shift
+//   type: Shift
+//   semitones: -12
+//   next: out
+struct Shift {
+    semitones: i8,
+    next: OptNode
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShiftOptions {
+    semitones: i8
+}
+
+impl Node for Shift {
+    fn call(&self, mut data: MidiData) {
+        data.note = data.note.saturating_add_signed(self.semitones).min(127);
+        self.next.call(data);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}
+
+impl NodeFactory for Shift {
+    type Options = ShiftOptions;
+
+    fn factory(_ctx: &Config, _name: &str, options: ShiftOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        Ok(Arc::new(Shift {
+            semitones: options.semitones,
+            next: RwLock::new(None)
+        }))
+    }
+}
+
+fn main() {
+    register::<Shift>("Shift");
+    mechsync::cli::main();
+}
@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::process::exit;
+use std::env;
+use std::io::Write;
+use clap::{Parser, Subcommand};
+use log::{error, info};
+use crate::config::Graph;
+use crate::control;
+use crate::control::Request;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(short, long)]
+    config_file: Option<PathBuf>,
+    /// Serve the control API on this Unix socket while running
+    #[arg(short, long)]
+    socket: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    // TODO: Implement debug logging to allow for better traceability within the graph
+    // #[arg(short, long, default_value = "false")]
+    // debug: bool
+}
+
+// runs the graph when no command is given
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the graph in Graphviz DOT format, annotated with latencies
+    Graph,
+    /// Send a request to a running instance through its control socket
+    Control {
+        #[command(subcommand)]
+        request: Request
+    }
+}
+
+// runs the command line interface, allowing downstream crates to register their node types beforehand
+pub fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info")
+    }
+    env_logger::builder().format(|fmt, record|
+        writeln!(fmt, "[{}@{}]:\n{}", record.level(), record.target(), record.args())
+    ).init();
+    run().unwrap_or_else(|err| {
+        error!(target: "Startup", "{}", err);
+        exit(1);
+    });
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = Args::try_parse()?;
+    if let Some(Command::Control { request }) = &args.command {
+        let socket = args.socket.as_ref().ok_or("--socket is required to send requests")?;
+        println!("{}", control::send(socket, request)?);
+        return Ok(());
+    }
+
+    let path = args.config_file.as_ref().ok_or("--config-file is required")?;
+    if let Some(Command::Graph) = args.command {
+        print!("{}", Graph::from_file_offline(path)?.to_dot());
+        return Ok(());
+    }
+    info!(target: "Startup", "Loading config");
+    let graph = Graph::from_file(path)?;
+    info!(target: "Startup", "Config loaded!");
+
+    let graph = Arc::new(RwLock::new(graph));
+    if let Some(socket) = &args.socket {
+        control::serve(graph.clone(), socket)?;
+    }
+    Graph::watch(&graph, path)
+}
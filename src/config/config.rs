@@ -1,14 +1,25 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use log::trace;
-use serde::{Deserialize, Deserializer};
-use crate::config::factories::TYPES;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_yml::{Mapping, Value};
+use crate::config::factories::factory;
 use crate::config::graph::Graph;
+use crate::control::Gate;
+use crate::data::{
+    MidiData, CHANNEL_PRESSURE, CONTROL_CHANGE, NOTE_ON, PITCH_BEND, POLY_PRESSURE, PROGRAM_CHANGE
+};
+use crate::node::Node;
 
 #[derive(Debug)]
-pub(crate) struct ConfigError {
+pub struct ConfigError {
     message: String
 }
 
@@ -21,22 +32,30 @@ impl Display for ConfigError {
 impl Error for ConfigError {}
 
 impl ConfigError {
-    pub(crate) fn new(message: &str) -> Self {
+    pub fn new(message: &str) -> Self {
         ConfigError {
             message: String::from(message)
         }
     }
 
-    pub(crate) fn of<E: Error>(err: E) -> Self {
+    pub fn of<E: Error>(err: E) -> Self {
         ConfigError {
             message: err.to_string()
         }
     }
 }
 
-pub(crate) struct Config {
+pub struct Config {
     nodes: Vec<NodeConfig>,
-    pub(super) delays: HashMap<String, Duration>
+    delays: HashMap<String, Duration>,
+    // the cumulative delay of each predecessor as its messages arrive at a node
+    arrivals: HashMap<String, Vec<(String, Duration)>>,
+    // every file the config was read from, including those it includes
+    sources: Vec<PathBuf>,
+    // the nodes whose cumulative delays the node being built depends on
+    delays_read: RefCell<Vec<String>>,
+    // whether nodes are built only to be described, without opening ports or starting scripts
+    offline: bool
 }
 
 #[derive(Deserialize)]
@@ -46,56 +65,790 @@ pub(crate) struct NodeConfig {
     pub(crate) type_: String,
     pub(crate) next: Option<String>,
 
-    // DelayNode
-    pub(crate) is_total: Option<bool>,
-    pub(crate) duration: Option<f32>,
+    // the remaining fields are deserialised by the node type's factory
+    #[serde(flatten)]
+    pub(crate) options: Mapping,
 
-    // DrumBot
-    pub(crate) arms: Option<Vec<ArmsConfig>>,
-
-    // PyNode
-    pub(crate) source: Option<String>
+    // the include or template the node was expanded from, if any
+    #[serde(skip)]
+    pub(crate) origin: Option<String>
 }
 
+// node types without any options of their own
 #[derive(Deserialize)]
-pub(crate) struct ArmsConfig(
-    #[serde(with = "tuple_vec_map")]
-    pub(crate) Vec<(u8, u8)>
-);
+#[serde(deny_unknown_fields)]
+pub struct NoOptions {}
+
+// either an exponent applied to the normalised velocity, or a list of (input, output) points to interpolate between
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum VelocityCurve {
+    Exponent(f32),
+    Points(Vec<(u8, u8)>)
+}
+
+impl VelocityCurve {
+    pub(crate) fn apply(&self, velocity: u8) -> u8 {
+        match self {
+            VelocityCurve::Exponent(exp) => {
+                (127f32 * (velocity as f32 / 127f32).powf(*exp)).round().clamp(1f32, 127f32) as u8
+            }
+            // points are given as plain bytes, so may lie beyond the range of a velocity, or be 0 and so turn the
+            // note-on into a note-off
+            VelocityCurve::Points(points) => VelocityCurve::interpolate(points, velocity).clamp(1, 127)
+        }
+    }
+
+    fn interpolate(points: &[(u8, u8)], velocity: u8) -> u8 {
+        let Some(&(first_in, first_out)) = points.first() else {
+            return velocity;
+        };
+        if velocity <= first_in {
+            return first_out;
+        }
+        for window in points.windows(2) {
+            let ((x0, y0), (x1, y1)) = (window[0], window[1]);
+            if velocity <= x1 {
+                let t = velocity.saturating_sub(x0) as f32 / x1.saturating_sub(x0).max(1) as f32;
+                return (y0 as f32 + t * (y1 as f32 - y0 as f32)).round() as u8;
+            }
+        }
+        points.last().map(|&(_x, y)| y).unwrap_or(velocity)
+    }
+}
+
+// 12 notes in a scale
+const TEMPERAMENT: f32 = 12f32;
+
+// the panning regression of a shuttle along a string, Δt = linear * Δd ^ exponential + quad * Δd ^ 2
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StringMovement {
+    pub(crate) linear: f32,
+    pub(crate) exponential: f32,
+    pub(crate) quadratic: f32
+}
+
+impl StringMovement {
+    // distance in terms of string length, i.e 0.5 means half string length, etc, between notes given in semitones
+    // above the open string
+    fn distance(a: u8, b: u8) -> f32 {
+        // convert into ratios based on equal temperament
+        let ratio = |note: u8| 2f32.powf(-(note as f32 / TEMPERAMENT));
+        (ratio(a) - ratio(b)).abs()
+    }
+
+    // the time taken to pan between notes given in semitones above the open string
+    pub(crate) fn time(&self, from: u8, to: u8) -> Duration {
+        if from == to {
+            return Duration::ZERO;
+        }
+        let dist = StringMovement::distance(from, to);
+        let time = self.linear * dist.powf(self.exponential) + dist * dist * self.quadratic;
+        Duration::try_from_secs_f32(time.max(0f32)).unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MessageType {
+    NoteOn,
+    NoteOff,
+    PolyPressure,
+    ControlChange,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend
+}
+
+impl MessageType {
+    fn of(data: &MidiData) -> Option<MessageType> {
+        if data.is_note_off() {
+            return Some(MessageType::NoteOff);
+        }
+        match data.instruction {
+            NOTE_ON => Some(MessageType::NoteOn),
+            POLY_PRESSURE => Some(MessageType::PolyPressure),
+            CONTROL_CHANGE => Some(MessageType::ControlChange),
+            PROGRAM_CHANGE => Some(MessageType::ProgramChange),
+            CHANNEL_PRESSURE => Some(MessageType::ChannelPressure),
+            PITCH_BEND => Some(MessageType::PitchBend),
+            _ => None
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub(crate) enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>)
+}
+
+impl<T: PartialEq> OneOrMany<T> {
+    fn contains(&self, value: &T) -> bool {
+        match self {
+            OneOrMany::One(one) => one == value,
+            OneOrMany::Many(many) => many.contains(value)
+        }
+    }
+}
+
+// a predicate on messages, where every given criterion must hold. Note and velocity ranges are inclusive, with the
+// velocity range only applying to note-ons so that their note-offs aren't excluded
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MessageMatch {
+    #[serde(rename = "type")]
+    types: Option<OneOrMany<MessageType>>,
+    channel: Option<OneOrMany<u8>>,
+    note: Option<(u8, u8)>,
+    velocity: Option<(u8, u8)>
+}
+
+impl MessageMatch {
+    pub(crate) fn matches(&self, data: &MidiData) -> bool {
+        let type_ = MessageType::of(data);
+        if let Some(types) = &self.types {
+            if !type_.is_some_and(|type_| types.contains(&type_)) {
+                return false;
+            }
+        }
+        if let Some(channels) = &self.channel {
+            if !channels.contains(&data.channel) {
+                return false;
+            }
+        }
+        if let Some((low, high)) = self.note {
+            if !data.is_note() || data.note < low || data.note > high {
+                return false;
+            }
+        }
+        if let Some((low, high)) = self.velocity {
+            if data.is_note_on() && (data.velocity < low || data.velocity > high) {
+                return false;
+            }
+        }
+        true
+    }
+}
 
 impl Config {
-    pub(crate) fn build(mut self) -> Result<Graph, ConfigError> {
-        let mut graph = Graph::new();
+    // the cumulative delay of every node leading up to the given node, along its slowest branch. Only predecessors
+    // declared before the node are accounted for
+    pub fn delay(&self, name: &str) -> Duration {
+        self.read(name);
+        self.delays.get(name).copied().unwrap_or_default()
+    }
+
+    // whether the graph is being built only to be described, in which case nodes which open ports or start scripts
+    // should stand in for them with a node of the same delay
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    // the cumulative delay of each of the given node's predecessors, by name
+    pub fn arrivals(&self, name: &str) -> Vec<(String, Duration)> {
+        self.read(name);
+        self.arrivals.get(name).cloned().unwrap_or_default()
+    }
+
+    fn read(&self, name: &str) {
+        let mut read = self.delays_read.borrow_mut();
+        if !read.iter().any(|read| read == name) {
+            read.push(String::from(name));
+        }
+    }
+
+    // the delays currently leading up to each of the given nodes
+    fn upstream(&self, names: &[String]) -> Upstream {
+        Upstream(names.iter().map(|name| Delays {
+            name: name.clone(),
+            delay: self.delays.get(name).copied().unwrap_or_default(),
+            arrivals: self.arrivals.get(name).cloned().unwrap_or_default()
+        }).collect())
+    }
+
+    pub(crate) fn build(self) -> Result<Graph, ConfigError> {
+        self.rebuild(None)
+    }
+
+    pub(crate) fn build_offline(mut self) -> Result<Graph, ConfigError> {
+        self.offline = true;
+        self.rebuild(None)
+    }
+
+    // builds the graph, reusing the nodes of a previous graph whose config is unchanged so that their ports and state
+    // are kept alive. The previous graph is left untouched should the config be invalid
+    pub(crate) fn rebuild(mut self, previous: Option<&Graph>) -> Result<Graph, ConfigError> {
+        let mut graph = Graph::new(self.sources.clone());
 
         for node in self.nodes.iter() {
             let type_ = node.type_.as_str();
-            let factory = TYPES.get(type_)
-                .ok_or(ConfigError::new(&format!("Unknown type: {}", type_)))?;
+            let mut fingerprint = Fingerprint {
+                type_: node.type_.clone(),
+                options: node.options.clone(),
+                upstream: Upstream::default(),
+                bound: node.next.is_some()
+            };
 
-            let dyn_node = factory(&self, &node)?;
-            trace!(target: "Config", "Loaded node {} of {}", node.name, type_);
-            if let Some(next) = &node.next {
-                self.delays.insert(next.clone(), dyn_node.delay() + *self.delays.get(&node.name).unwrap_or(&Duration::from_secs(0)));
+            let reused = previous.and_then(|previous| {
+                previous.reuse(&node.name, &fingerprint, |prev| self.upstream(&prev.names()))
+            });
+            let dyn_node = match reused {
+                Some((dyn_node, upstream)) => {
+                    trace!(target: "Config", "Kept node {} of {}", node.name, type_);
+                    fingerprint.upstream = upstream;
+                    dyn_node
+                }
+                None => {
+                    self.delays_read.take();
+                    let factory = factory(type_).ok_or(ConfigError::new(&format!(
+                        "Unknown type for {}{}: {}", node.name, describe(node.origin.as_deref()), type_
+                    )))?;
+                    let dyn_node = factory(&self, node).map_err(|err| match &node.origin {
+                        Some(origin) => ConfigError::new(&format!("{} (from {})", err.message, origin)),
+                        None => err
+                    })?;
+                    trace!(target: "Config", "Loaded node {} of {}", node.name, type_);
+                    fingerprint.upstream = self.upstream(&self.delays_read.take());
+                    Arc::new(Gate::new(dyn_node))
+                }
+            };
+            let cumulative = *self.delays.get(&node.name).unwrap_or(&Duration::from_secs(0));
+            for next in node.next.iter().cloned().chain(dyn_node.targets()) {
                 trace!(target: "Config", "Bound {} -> {}", node.name, next);
+                let arrival = dyn_node.delay_to(&next) + cumulative;
+                let delay = self.delays.entry(next.clone()).or_default();
+                *delay = arrival.max(*delay);
+                self.arrivals.entry(next).or_default().push((node.name.clone(), arrival));
             }
             graph.insert(
                 node.name.as_str(),
-                dyn_node
+                dyn_node,
+                fingerprint
             )
         }
 
+        // every binding is checked before any are made, as reused nodes are still running within the previous graph
+        for node in self.nodes.iter() {
+            let targets = graph.get(&node.name).map(|dyn_node| dyn_node.targets()).unwrap_or_default();
+            for next in node.next.iter().chain(targets.iter()) {
+                if !graph.contains(next) {
+                    let message = format!("couldn't locate node: {}{}", next, describe(node.origin.as_deref()));
+                    return Err(ConfigError::new(&message));
+                }
+            }
+        }
         for node in self.nodes.iter() {
             if let Some(next) = &node.next {
                 graph.bind(node.name.as_str(), next.as_str())?;
             }
+            for target in graph.get(&node.name).map(|dyn_node| dyn_node.targets()).unwrap_or_default() {
+                graph.bind_target(node.name.as_str(), target.as_str())?;
+            }
         }
+        graph.resolve();
         Ok(graph)
     }
 }
 
-impl<'de> Deserialize<'de> for Config {
-    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
-        let nodes = Vec::deserialize(d)?;
-        Ok(Config { nodes, delays: HashMap::new() })
+// the delays leading up to a node
+#[derive(PartialEq, Clone)]
+struct Delays {
+    name: String,
+    delay: Duration,
+    arrivals: Vec<(String, Duration)>
+}
+
+// the delays leading up to each node a node was built from
+#[derive(PartialEq, Clone, Default)]
+pub(crate) struct Upstream(Vec<Delays>);
+
+impl Upstream {
+    fn names(&self) -> Vec<String> {
+        self.0.iter().map(|delays| delays.name.clone()).collect()
+    }
+}
+
+// everything a node is built from, such that nodes with matching fingerprints are interchangeable
+pub(crate) struct Fingerprint {
+    type_: String,
+    options: Mapping,
+    // only those the node's factory made use of
+    upstream: Upstream,
+    // nodes cannot be unbound, so those losing their successor are rebuilt
+    bound: bool
+}
+
+impl Fingerprint {
+    // whether the node may be reused, given the delays now leading up to the nodes it was built from
+    pub(crate) fn matches(&self, other: &Fingerprint, upstream: impl FnOnce(&Upstream) -> Upstream) -> bool {
+        self.type_ == other.type_
+            && self.options == other.options
+            && self.bound == other.bound
+            && upstream(&self.upstream) == self.upstream
+    }
+
+    pub(crate) fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    pub(crate) fn type_name(&self) -> &str {
+        &self.type_
+    }
+}
+
+// ---------------------
+// Includes and Templates
+// ---------------------
+// entries of a config are either nodes, or one of the following, which are expanded into nodes before building:
+// - `include: path` splices in the entries of another file, relative to the including file
+// - `template: name` with `params` and `nodes` defines a template, whose `${param}`s are replaced on `use`
+// - `use: name` expands a previously defined template, with the remaining keys given as its params
+// - `chain: prefix` expands to an Input -> instrument -> DelayNode -> Output chain named after the prefix
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+struct Template {
+    params: Vec<String>,
+    nodes: Vec<Value>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateConfig {
+    template: String,
+    #[serde(default)]
+    params: Vec<String>,
+    nodes: Vec<Value>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainConfig {
+    chain: String,
+    // the instrument's type and options, which is named after the chain
+    instrument: Mapping,
+    // the total delay from the input to the output, omitting the DelayNode when not given
+    delay: Option<f32>
+}
+
+#[derive(Default)]
+struct Expansion {
+    templates: HashMap<String, Template>,
+    includes: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+    nodes: Vec<NodeConfig>
+}
+
+impl Expansion {
+    fn expand(&mut self, entries: Vec<Value>, dir: &Path, origin: Option<&str>, depth: usize) -> Result<(), ConfigError> {
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err(ConfigError::new(&format!(
+                "Expansion nested more than {} levels deep{}", MAX_EXPANSION_DEPTH, describe(origin)
+            )));
+        }
+        for (index, entry) in entries.into_iter().enumerate() {
+            let key = |key: &str| entry.as_mapping().and_then(|mapping| mapping.get(key)).cloned();
+            if let Some(include) = key("include") {
+                self.include(include, dir, origin, depth)?;
+            } else if key("template").is_some() {
+                let config: TemplateConfig = from_entry(entry, index, origin)?;
+                self.templates.insert(config.template, Template { params: config.params, nodes: config.nodes });
+            } else if let Some(name) = key("use") {
+                let name = name.as_str()
+                    .ok_or(ConfigError::new(&format!("Template name must be a string{}", describe(origin))))?;
+                let nodes = self.instantiate(name, entry.as_mapping().unwrap(), origin)?;
+                let origin = nest(format!("template `{}`", name), origin);
+                self.expand(nodes, dir, Some(&origin), depth + 1)?;
+            } else if key("chain").is_some() {
+                let config: ChainConfig = from_entry(entry, index, origin)?;
+                let origin = nest(format!("chain `{}`", config.chain), origin);
+                self.expand(chain(config), dir, Some(&origin), depth + 1)?;
+            } else {
+                let mut node: NodeConfig = from_entry(entry, index, origin)?;
+                if self.nodes.iter().any(|other| other.name == node.name) {
+                    return Err(ConfigError::new(&format!("Duplicate node name: {}{}", node.name, describe(origin))));
+                }
+                node.origin = origin.map(String::from);
+                self.nodes.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    fn include(&mut self, include: Value, dir: &Path, origin: Option<&str>, depth: usize) -> Result<(), ConfigError> {
+        let paths: Vec<String> = match include {
+            Value::String(path) => vec![path],
+            include => serde_yml::from_value(include).map_err(|err| ConfigError::new(&format!(
+                "Include must be a path or list of paths{}: {}", describe(origin), err
+            )))?
+        };
+        for path in paths {
+            let path = dir.join(path);
+            let canonical = path.canonicalize().map_err(|err| ConfigError::new(&format!(
+                "Unable to include {}{}: {}", path.display(), describe(origin), err
+            )))?;
+            if self.includes.contains(&canonical) {
+                return Err(ConfigError::new(&format!("Include cycle at {}{}", path.display(), describe(origin))));
+            }
+            let yaml = read_to_string(&canonical).map_err(ConfigError::of)?;
+            let entries: Vec<Value> = serde_yml::from_str(&yaml)
+                .map_err(|err| ConfigError::new(&format!("{}: {}", path.display(), err)))?;
+
+            self.includes.push(canonical.clone());
+            self.sources.push(canonical.clone());
+            let origin = nest(format!("include `{}`", path.display()), origin);
+            self.expand(entries, canonical.parent().unwrap_or(dir), Some(&origin), depth + 1)?;
+            self.includes.pop();
+        }
+        Ok(())
+    }
+
+    fn instantiate(&self, name: &str, entry: &Mapping, origin: Option<&str>) -> Result<Vec<Value>, ConfigError> {
+        let template = self.templates.get(name)
+            .ok_or(ConfigError::new(&format!("Unknown template: {}{}", name, describe(origin))))?;
+        let mut args = HashMap::new();
+        for (key, value) in entry.iter() {
+            let key = key.as_str().unwrap_or_default();
+            if key == "use" {
+                continue;
+            }
+            if !template.params.iter().any(|param| param == key) {
+                return Err(ConfigError::new(&format!("Template {} has no param {}{}", name, key, describe(origin))));
+            }
+            args.insert(key, value);
+        }
+        if let Some(missing) = template.params.iter().find(|param| !args.contains_key(param.as_str())) {
+            return Err(ConfigError::new(&format!("Template {} is missing param {}{}", name, missing, describe(origin))));
+        }
+        Ok(template.nodes.iter().map(|node| substitute(node, &args)).collect())
+    }
+}
+
+fn from_entry<T: DeserializeOwned>(entry: Value, index: usize, origin: Option<&str>) -> Result<T, ConfigError> {
+    let name = entry.as_mapping()
+        .and_then(|mapping| mapping.get("name"))
+        .and_then(Value::as_str)
+        .map(String::from)
+        .unwrap_or_else(|| format!("entry {}", index));
+    serde_yml::from_value(entry).map_err(|err| ConfigError::new(&format!("{}{}: {}", name, describe(origin), err)))
+}
+
+fn describe(origin: Option<&str>) -> String {
+    origin.map(|origin| format!(" (from {})", origin)).unwrap_or_default()
+}
+
+fn nest(expansion: String, origin: Option<&str>) -> String {
+    match origin {
+        Some(origin) => format!("{} in {}", expansion, origin),
+        None => expansion
+    }
+}
+
+// strings consisting of a single `${param}` take on the param's value as is, allowing numbers and nested options to
+// be passed through, whereas params within longer strings are replaced by their text
+fn substitute(value: &Value, args: &HashMap<&str, &Value>) -> Value {
+    match value {
+        Value::String(text) => {
+            if let Some(arg) = text.strip_prefix("${").and_then(|rest| rest.strip_suffix('}')).and_then(|param| args.get(param)) {
+                return (*arg).clone();
+            }
+            let mut text = text.clone();
+            for (param, arg) in args {
+                let arg = match arg {
+                    Value::String(arg) => arg.clone(),
+                    arg => serde_yml::to_string(arg).unwrap_or_default().trim_end().to_string()
+                };
+                text = text.replace(&format!("${{{}}}", param), &arg);
+            }
+            Value::String(text)
+        }
+        Value::Sequence(values) => Value::Sequence(values.iter().map(|value| substitute(value, args)).collect()),
+        Value::Mapping(mapping) => Value::Mapping(mapping.iter()
+            .map(|(key, value)| (substitute(key, args), substitute(value, args)))
+            .collect()),
+        value => value.clone()
     }
-}
\ No newline at end of file
+}
+
+fn chain(config: ChainConfig) -> Vec<Value> {
+    let prefix = config.chain;
+    let node = |name: &str, type_: &str, next: Option<&str>| {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::from("name"), Value::from(name));
+        mapping.insert(Value::from("type"), Value::from(type_));
+        if let Some(next) = next {
+            mapping.insert(Value::from("next"), Value::from(next));
+        }
+        mapping
+    };
+    let input = format!("{} Input", prefix);
+    let delay = format!("{} Delay", prefix);
+    let output = format!("{} Output", prefix);
+
+    let mut instrument = config.instrument;
+    instrument.insert(Value::from("name"), Value::from(prefix.as_str()));
+    instrument.insert(Value::from("next"), Value::from(if config.delay.is_some() { &delay } else { &output }.as_str()));
+
+    let mut nodes = vec![Value::Mapping(node(&input, "Input", Some(&prefix))), Value::Mapping(instrument)];
+    if let Some(duration) = config.delay {
+        let mut delay = node(&delay, "DelayNode", Some(&output));
+        delay.insert(Value::from("is_total"), Value::from(true));
+        delay.insert(Value::from("duration"), Value::from(duration));
+        nodes.push(Value::Mapping(delay));
+    }
+    nodes.push(Value::Mapping(node(&output, "Output", None)));
+    nodes
+}
+
+impl Config {
+    pub fn from_yaml(yaml: &str, dir: &Path) -> Result<Self, ConfigError> {
+        Config::expand(yaml, dir, Expansion::default())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let path = path.canonicalize().map_err(ConfigError::of)?;
+        let yaml = read_to_string(&path).map_err(ConfigError::of)?;
+        let expansion = Expansion { includes: vec![path.clone()], sources: vec![path.clone()], ..Expansion::default() };
+        Config::expand(&yaml, path.parent().unwrap_or(Path::new(".")), expansion)
+    }
+
+    fn expand(yaml: &str, dir: &Path, mut expansion: Expansion) -> Result<Self, ConfigError> {
+        let entries: Vec<Value> = serde_yml::from_str(yaml).map_err(ConfigError::of)?;
+        expansion.expand(entries, dir, None, 0)?;
+        Ok(Config {
+            nodes: expansion.nodes,
+            delays: HashMap::new(),
+            arrivals: HashMap::new(),
+            sources: expansion.sources,
+            delays_read: RefCell::new(Vec::new()),
+            offline: false
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+    use crate::data::NOTE_OFF;
+    use crate::node::testing::Recorder;
+    use crate::transforms::Filter;
+    use super::*;
+
+    fn names(config: &Config) -> Vec<&str> {
+        config.nodes.iter().map(|node| node.name.as_str()).collect()
+    }
+
+    fn node<'a>(config: &'a Config, name: &str) -> &'a NodeConfig {
+        config.nodes.iter().find(|node| node.name == name).unwrap()
+    }
+
+    // a fresh directory for each test's files
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mechsync-{}-{}", test, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn template_params_are_substituted() {
+        let config = Config::from_yaml(r#"
+            - template: delayed
+              params: [name, duration]
+              nodes:
+                - name: ${name} Delay
+                  type: DelayNode
+                  duration: ${duration}
+            - use: delayed
+              name: Bass
+              duration: 1.5
+        "#, Path::new(".")).unwrap();
+        let delay = node(&config, "Bass Delay");
+        assert_eq!(delay.type_, "DelayNode");
+        // whole strings keep the type of their param
+        assert_eq!(delay.options.get("duration"), Some(&Value::from(1.5)));
+        assert_eq!(delay.origin.as_deref(), Some("template `delayed`"));
+    }
+
+    #[test]
+    fn template_params_must_match() {
+        let template = r#"
+            - template: output
+              params: [name]
+              nodes:
+                - name: ${name}
+                  type: Output
+        "#;
+        let unknown = Config::from_yaml(&format!("{}\n            - use: output\n              name: A\n              other: B", template), Path::new("."));
+        assert!(unknown.err().unwrap().to_string().contains("has no param other"));
+        let missing = Config::from_yaml(&format!("{}\n            - use: output", template), Path::new("."));
+        assert!(missing.err().unwrap().to_string().contains("is missing param name"));
+    }
+
+    #[test]
+    fn recursive_templates_are_limited() {
+        let config = Config::from_yaml(r#"
+            - template: forever
+              nodes:
+                - use: forever
+            - use: forever
+        "#, Path::new("."));
+        assert!(config.err().unwrap().to_string().contains("levels deep"));
+    }
+
+    #[test]
+    fn chains_expand_to_nodes() {
+        let config = Config::from_yaml(r#"
+            - chain: Bass
+              instrument:
+                type: MechBass
+              delay: 2
+            - chain: Drums
+              instrument:
+                type: DrumBot
+                arms: []
+        "#, Path::new(".")).unwrap();
+        assert_eq!(names(&config), ["Bass Input", "Bass", "Bass Delay", "Bass Output", "Drums Input", "Drums", "Drums Output"]);
+        assert_eq!(node(&config, "Bass Input").next.as_deref(), Some("Bass"));
+        assert_eq!(node(&config, "Bass").next.as_deref(), Some("Bass Delay"));
+        let delay = node(&config, "Bass Delay");
+        assert_eq!(delay.options.get("is_total"), Some(&Value::from(true)));
+        assert_eq!(delay.next.as_deref(), Some("Bass Output"));
+        // without a delay, the instrument leads straight to the output
+        assert_eq!(node(&config, "Drums").next.as_deref(), Some("Drums Output"));
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let dir = dir("includes");
+        create_dir_all(dir.join("nested")).unwrap();
+        write(dir.join("main.yml"), "- include: nested/outer.yml\n- name: Main\n  type: DebugNode\n").unwrap();
+        write(dir.join("nested/outer.yml"), "- include: inner.yml\n").unwrap();
+        write(dir.join("nested/inner.yml"), "- name: Inner\n  type: DebugNode\n").unwrap();
+
+        let config = Config::from_file(&dir.join("main.yml")).unwrap();
+        assert_eq!(names(&config), ["Inner", "Main"]);
+        assert_eq!(config.sources.len(), 3);
+        assert!(node(&config, "Inner").origin.as_deref().unwrap().starts_with("include `"));
+    }
+
+    #[test]
+    fn include_cycles_are_rejected() {
+        let dir = dir("cycles");
+        write(dir.join("a.yml"), "- include: b.yml\n").unwrap();
+        write(dir.join("b.yml"), "- include: a.yml\n").unwrap();
+        let config = Config::from_file(&dir.join("a.yml"));
+        assert!(config.err().unwrap().to_string().contains("Include cycle"));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let config = Config::from_yaml(r#"
+            - chain: Bass
+              instrument:
+                type: MechBass
+            - name: Bass
+              type: DebugNode
+        "#, Path::new("."));
+        assert!(config.err().unwrap().to_string().contains("Duplicate node name: Bass"));
+    }
+
+    #[test]
+    fn inline_params_are_replaced_by_text() {
+        let mut args = HashMap::new();
+        let number = Value::from(3);
+        args.insert("index", &number);
+        let value = substitute(&Value::from("Voice ${index} of ${missing}"), &args);
+        assert_eq!(value, Value::from("Voice 3 of ${missing}"));
+    }
+
+    #[test]
+    fn velocity_exponents_curve_the_normalised_velocity() {
+        let curve = VelocityCurve::Exponent(2.0);
+        assert_eq!(curve.apply(127), 127);
+        assert_eq!(curve.apply(64), 32);
+        // quiet notes still sound
+        assert_eq!(curve.apply(1), 1);
+    }
+
+    #[test]
+    fn velocity_points_are_interpolated_and_clamped() {
+        let curve = VelocityCurve::Points(vec![(20, 40), (100, 120), (120, 255)]);
+        assert_eq!(curve.apply(0), 40);
+        assert_eq!(curve.apply(60), 80);
+        assert_eq!(curve.apply(100), 120);
+        // points beyond the range of a velocity are clamped to it
+        assert_eq!(curve.apply(127), 127);
+        assert_eq!(VelocityCurve::Points(Vec::new()).apply(64), 64);
+    }
+
+    #[test]
+    fn velocity_points_never_silence_a_note() {
+        let curve = VelocityCurve::Points(vec![(0, 0), (127, 127)]);
+        assert_eq!(curve.apply(0), 1);
+        assert_eq!(curve.apply(1), 1);
+        assert_eq!(VelocityCurve::Points(vec![(64, 0)]).apply(100), 1);
+    }
+
+    fn matcher(yaml: &str) -> MessageMatch {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    fn message(instruction: u8, channel: u8, note: u8, velocity: u8) -> MidiData {
+        MidiData { instruction, channel, note, velocity }
+    }
+
+    #[test]
+    fn every_criterion_must_match() {
+        let matcher = matcher("{ type: note_on, channel: [1, 2], note: [36, 48] }");
+        assert!(matcher.matches(&message(NOTE_ON, 2, 40, 100)));
+        assert!(!matcher.matches(&message(NOTE_ON, 3, 40, 100)));
+        assert!(!matcher.matches(&message(NOTE_ON, 1, 50, 100)));
+        assert!(!matcher.matches(&message(CONTROL_CHANGE, 1, 40, 100)));
+        // note-ons without velocity are note-offs
+        assert!(!matcher.matches(&message(NOTE_ON, 1, 40, 0)));
+        assert!(MessageMatch::default().matches(&message(PITCH_BEND, 0, 0, 64)));
+    }
+
+    #[test]
+    fn velocity_ranges_only_apply_to_note_ons() {
+        let matcher = matcher("{ velocity: [64, 127] }");
+        assert!(matcher.matches(&message(NOTE_ON, 0, 40, 100)));
+        assert!(!matcher.matches(&message(NOTE_ON, 0, 40, 20)));
+        assert!(matcher.matches(&message(NOTE_OFF, 0, 40, 20)));
+    }
+
+    #[test]
+    fn note_ranges_exclude_messages_without_notes() {
+        let matcher = matcher("{ note: [0, 127] }");
+        assert!(matcher.matches(&message(POLY_PRESSURE, 0, 40, 10)));
+        assert!(!matcher.matches(&message(CONTROL_CHANGE, 0, 40, 10)));
+    }
+
+    #[test]
+    fn inverted_filters_pass_the_note_offs_of_passed_notes() {
+        let filter = Filter::new(matcher("{ velocity: [0, 63] }"), true);
+        let recorder = Recorder::after(&filter);
+        filter.call(message(NOTE_ON, 0, 40, 100));
+        filter.call(message(NOTE_ON, 0, 41, 20));
+        filter.call(message(NOTE_OFF, 0, 40, 0));
+        filter.call(message(NOTE_OFF, 0, 41, 0));
+        assert_eq!(recorder.take(), [message(NOTE_ON, 0, 40, 100), message(NOTE_OFF, 0, 40, 0)]);
+    }
+
+    #[test]
+    fn filters_drop_orphan_note_offs() {
+        let filter = Filter::new(matcher("{ channel: 1 }"), false);
+        let recorder = Recorder::after(&filter);
+        filter.call(message(NOTE_OFF, 1, 40, 0));
+        filter.call(message(NOTE_ON, 1, 40, 100));
+        filter.call(message(NOTE_ON, 1, 40, 0));
+        filter.call(message(NOTE_OFF, 1, 40, 0));
+        assert_eq!(recorder.take(), [message(NOTE_ON, 1, 40, 100), message(NOTE_ON, 1, 40, 0)]);
+        // other messages are still matched as they are
+        filter.call(message(CONTROL_CHANGE, 1, 7, 100));
+        filter.call(message(CONTROL_CHANGE, 2, 7, 100));
+        assert_eq!(recorder.take(), [message(CONTROL_CHANGE, 1, 7, 100)]);
+    }
+}
@@ -1,70 +1,238 @@
 use std::collections::HashMap;
+#[cfg(feature = "rhai")]
 use std::fs::read_to_string;
+#[cfg(feature = "python")]
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde_yml::Value;
 
-use crate::config::config::{Config, ConfigError, NodeConfig};
-use crate::instruments::{DrumBot, MechBass, PyNode};
+use crate::config::config::{Config, ConfigError, NoOptions, NodeConfig};
+use crate::instruments::{
+    ArmsConfig, DrumBot, DrumBotOptions, FrettedString, FrettedStringOptions, Instrument, InstrumentOptions, MechBass,
+    VoiceAllocator, VoiceAllocatorOptions
+};
+#[cfg(feature = "python")]
+use crate::instruments::{Execution, PyNode, PyNodeOptions};
+#[cfg(feature = "rhai")]
+use crate::instruments::{RhaiNode, RhaiNodeOptions};
+#[cfg(feature = "wasm")]
+use crate::instruments::{WasmNode, WasmNodeOptions};
 use crate::midi::{Input, Output};
-use crate::node::{DebugNode, DelayNode, Node};
+use crate::node::{DebugNode, DelayNode, DelayNodeOptions, Node};
+use crate::transforms::{
+    Arpeggiator, ArpeggiatorOptions, ChannelMap, ChannelMapOptions, ChordMode, ChordStyle, Clock, ClockOptions, Filter,
+    FilterOptions, Grid, Humanise, Merge, NoteGuard, NoteGuardOptions, Quantise, QuantiseOptions, Router, RouterOptions,
+    Transpose, TransposeOptions, VelocityMap, VelocityMapOptions
+};
 
 macro_rules! types {
     ( $( $typename:ident ),* ) => {
-        HashMap::from([$((stringify!($typename), $typename::factory as FactoryFunction), )*])
+        HashMap::from([$((String::from(stringify!($typename)), build::<$typename> as FactoryFunction), )*])
+    }
+}
+
+// registers a type whose implementation has been compiled out, so that configs using it fail with a meaningful error
+#[allow(unused_macros)]
+macro_rules! unavailable {
+    ( $typename:ident, $feature:literal ) => {
+        (
+            String::from(stringify!($typename)),
+            (|_ctx, _config| Err(ConfigError::new(concat!(
+                stringify!($typename), " requires the ", $feature, " feature"
+            )))) as FactoryFunction
+        )
     }
 }
 
 // -----------------------
 // Factory Implementations
 // -----------------------
-pub(super) static TYPES: Lazy<HashMap<&'static str, FactoryFunction>> = Lazy::new(|| types![
-    Input,
-    Output,
-    MechBass,
-    DrumBot,
-    PyNode,
-    DelayNode,
-    DebugNode
-]);
+static TYPES: Lazy<RwLock<HashMap<String, FactoryFunction>>> = Lazy::new(|| {
+    let mut types = types![
+        Input,
+        Output,
+        MechBass,
+        DrumBot,
+        FrettedString,
+        VoiceAllocator,
+        Instrument,
+        DelayNode,
+        DebugNode,
+        Transpose,
+        VelocityMap,
+        ChannelMap,
+        Router,
+        Filter,
+        Merge,
+        NoteGuard,
+        Arpeggiator,
+        Quantise,
+        Clock
+    ];
+
+    #[cfg(feature = "python")]
+    types.extend(types![PyNode]);
+    #[cfg(not(feature = "python"))]
+    types.extend([unavailable!(PyNode, "python")]);
+
+    #[cfg(feature = "rhai")]
+    types.extend(types![RhaiNode]);
+    #[cfg(not(feature = "rhai"))]
+    types.extend([unavailable!(RhaiNode, "rhai")]);
+
+    #[cfg(feature = "wasm")]
+    types.extend(types![WasmNode]);
+    #[cfg(not(feature = "wasm"))]
+    types.extend([unavailable!(WasmNode, "wasm")]);
+
+    RwLock::new(types)
+});
 
 impl NodeFactory for Input {
-    fn factory(_ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        let node = Input::new(config.name.as_str()).map_err(ConfigError::of)?;
+    type Options = NoOptions;
+
+    fn factory(ctx: &Config, name: &str, _options: NoOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if ctx.offline() {
+            return Ok(Arc::new(DebugNode::new(name)));
+        }
+        let node = Input::new(name).map_err(ConfigError::of)?;
         Ok(Arc::new(node))
     }
 }
 
 impl NodeFactory for Output {
-    fn factory(_ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        let node = Output::new(config.name.as_str()).map_err(ConfigError::of)?;
+    type Options = NoOptions;
+
+    fn factory(ctx: &Config, name: &str, _options: NoOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if ctx.offline() {
+            return Ok(Arc::new(DebugNode::new(name)));
+        }
+        let node = Output::new(name).map_err(ConfigError::of)?;
         Ok(Arc::new(node))
     }
 }
 
 impl NodeFactory for MechBass {
-    fn factory(_ctx: &Config, _config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        Ok(Arc::new(MechBass::new()))
+    type Options = NoOptions;
+
+    fn factory(_ctx: &Config, name: &str, _options: NoOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        Ok(Arc::new(MechBass::build(name)))
+    }
+}
+
+impl NodeFactory for FrettedString {
+    type Options = FrettedStringOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: FrettedStringOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.strings.is_empty() {
+            return Err(ConfigError::new(&format!("{}: at least one string is required", name)));
+        }
+        for (index, string) in options.strings.iter().enumerate() {
+            let error = |message: &str| ConfigError::new(&format!("{}.strings[{}]: {}", name, index, message));
+            let frets = string.frets.unwrap_or(options.frets);
+            if frets == 0 || string.tuning as u16 + frets as u16 > 128 {
+                return Err(error("every fret must play a note within 0-127"));
+            }
+            if string.channel.is_some_and(|channel| channel > 15) {
+                return Err(error("channel must be within 0-15"));
+            }
+        }
+        let window = Duration::try_from_secs_f32(options.window)
+            .map_err(|_| ConfigError::new(&format!("{}: window must not be negative", name)))?;
+        let calibration = options.shuttle.unwrap_or(MechBass::CALIBRATION);
+        Ok(Arc::new(FrettedString::new(name, &options.strings, options.frets, calibration, window, options.max_span)))
     }
 }
 
 impl NodeFactory for DrumBot {
-    fn factory(_ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        let arms = config.arms.as_ref().ok_or(ConfigError::new("Arms missing"))?;
-        Ok(Arc::new(DrumBot::new(arms)))
+    type Options = DrumBotOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: DrumBotOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        for arm in options.arms.iter() {
+            let ArmsConfig::Named { name: arm, drums } = arm else {
+                continue;
+            };
+            for drum in drums {
+                Duration::try_from_secs_f32(drum.travel_time).map_err(|_| ConfigError::new(&format!(
+                    "{}.{}.{}: travel_time must be finite and not negative, got {}", name, arm, drum.name, drum.travel_time
+                )))?;
+            }
+        }
+        Ok(Arc::new(DrumBot::new(&options.arms)))
+    }
+}
+
+impl NodeFactory for VoiceAllocator {
+    type Options = VoiceAllocatorOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: VoiceAllocatorOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.voices.is_empty() {
+            return Err(ConfigError::new(&format!("{}: at least one voice is required", name)));
+        }
+        for (index, voice) in options.voices.iter().enumerate() {
+            let error = |message: &str| ConfigError::new(&format!("{}.voices[{}]: {}", name, index, message));
+            if voice.range.is_none() && voice.notes.is_empty() {
+                return Err(error("either range or notes is required"));
+            }
+            if voice.range.is_some_and(|(low, high)| high < low || high > 127) || voice.notes.iter().any(|note| *note > 127) {
+                return Err(error("notes must be within 0-127, with ranges ordered"));
+            }
+            if voice.channel.is_some_and(|channel| channel > 15) {
+                return Err(error("channel must be within 0-15"));
+            }
+        }
+        let movements = options.voices.iter().filter_map(|voice| voice.movement.as_ref()).chain([&options.movement]);
+        for movement in movements {
+            let mut terms = [movement.fixed, movement.per_semitone].into_iter()
+                .chain(movement.string.iter().flat_map(|string| [string.linear, string.exponential, string.quadratic]));
+            if terms.any(|term| !term.is_finite()) || movement.fixed < 0.0 || movement.per_semitone < 0.0 {
+                return Err(ConfigError::new(&format!("{}: movement times must be finite and not negative", name)));
+            }
+        }
+        Ok(Arc::new(VoiceAllocator::new(name, &options.voices, &options.movement, options.steal)))
+    }
+}
+
+impl NodeFactory for Instrument {
+    type Options = InstrumentOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: InstrumentOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.actuators.is_empty() {
+            return Err(ConfigError::new(&format!("{}: at least one actuator is required", name)));
+        }
+        for (index, actuator) in options.actuators.iter().enumerate() {
+            let error = |message: &str| ConfigError::new(&format!("{}.actuators[{}]: {}", name, index, message));
+            if actuator.range.is_none() && actuator.notes.is_empty() {
+                return Err(error("either range or notes is required"));
+            }
+            let mut notes = actuator.notes.iter().chain(&actuator.output);
+            if actuator.range.is_some_and(|(low, high)| high < low || high > 127) || notes.any(|note| *note > 127) {
+                return Err(error("notes must be within 0-127, with ranges ordered"));
+            }
+            if actuator.channel.is_some_and(|channel| channel > 15) {
+                return Err(error("channel must be within 0-15"));
+            }
+            let valid = |secs: f32| Duration::try_from_secs_f32(secs).is_ok();
+            if !valid(actuator.latency) || !valid(actuator.retrigger) {
+                return Err(error("latency and retrigger must be finite and not negative"));
+            }
+        }
+        Ok(Arc::new(Instrument::new(name, &options.actuators, options.note_offs)))
     }
 }
 
 impl NodeFactory for DelayNode {
-    fn factory(ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        let duration_raw = Duration::from_secs_f32(
-            config.duration.ok_or(ConfigError::new("Duration missing"))?
-        );
-        let is_total = config.is_total.unwrap_or(false);
-
-        let duration = if is_total {
-            let prev_duration = *ctx.delays.get(&config.name).unwrap();
+    type Options = DelayNodeOptions;
+
+    fn factory(ctx: &Config, name: &str, options: DelayNodeOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let duration_raw = Duration::try_from_secs_f32(options.duration)
+            .map_err(|_| ConfigError::new(&format!("{}: duration must be finite and not negative, got {}", name, options.duration)))?;
+
+        let duration = if options.is_total {
+            let prev_duration = ctx.delay(name);
             if prev_duration > duration_raw {
                 return Err(ConfigError::new(format!(
                     "Previous duration longer than total duration required ({:?} > {:?})",
@@ -82,26 +250,287 @@ impl NodeFactory for DelayNode {
 }
 
 impl NodeFactory for DebugNode {
-    fn factory(_ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        Ok(Arc::new(DebugNode::new(config.name.as_str())))
+    type Options = NoOptions;
+
+    fn factory(_ctx: &Config, name: &str, _options: NoOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        Ok(Arc::new(DebugNode::new(name)))
+    }
+}
+
+impl NodeFactory for Transpose {
+    type Options = TransposeOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: TransposeOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if !(-127..=127).contains(&options.semitones) {
+            return Err(ConfigError::new(&format!(
+                "{}: semitones must be within -127-127, got {}", name, options.semitones
+            )));
+        }
+        if let Some((low, high)) = options.range {
+            // narrower ranges may not contain every pitch class
+            if high > 127 || high < low || high - low < 11 {
+                return Err(ConfigError::new(&format!(
+                    "{}: range must span at least an octave within 0-127, got {}-{}", name, low, high
+                )));
+            }
+        }
+        Ok(Arc::new(Transpose::new(name, options.semitones, options.range)))
+    }
+}
+
+impl NodeFactory for VelocityMap {
+    type Options = VelocityMapOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: VelocityMapOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.min > options.max || options.max > 127 {
+            return Err(ConfigError::new(&format!(
+                "{}: min and max must be ordered within 0-127, got {}-{}", name, options.min, options.max
+            )));
+        }
+        Ok(Arc::new(VelocityMap::new(options.curve, options.min, options.max, options.fixed)))
+    }
+}
+
+impl NodeFactory for ChannelMap {
+    type Options = ChannelMapOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: ChannelMapOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.channel.is_some() && !options.channels.is_empty() {
+            return Err(ConfigError::new(&format!("{}: only one of channel and channels may be given", name)));
+        }
+        let invalid = options.channel.into_iter()
+            .chain(options.channels.iter().flat_map(|&(input, output)| [input, output]))
+            .find(|channel| *channel > 15);
+        if let Some(channel) = invalid {
+            return Err(ConfigError::new(&format!("{}: channels must be within 0-15, got {}", name, channel)));
+        }
+        Ok(Arc::new(ChannelMap::new(options.channel, &options.channels)))
+    }
+}
+
+impl NodeFactory for Router {
+    type Options = RouterOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: RouterOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let rules = options.rules.into_iter().map(|rule| (rule.matcher, rule.to)).collect();
+        Ok(Arc::new(Router::new(name, rules)))
+    }
+}
+
+impl NodeFactory for Filter {
+    type Options = FilterOptions;
+
+    fn factory(_ctx: &Config, _name: &str, options: FilterOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        Ok(Arc::new(Filter::new(options.matcher, options.invert)))
+    }
+}
+
+impl NodeFactory for Merge {
+    type Options = NoOptions;
+
+    fn factory(_ctx: &Config, _name: &str, _options: NoOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        Ok(Arc::new(Merge::new()))
+    }
+}
+
+impl NodeFactory for NoteGuard {
+    type Options = NoteGuardOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: NoteGuardOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let max_length = Duration::try_from_secs_f32(options.max_length)
+            .ok()
+            .filter(|max_length| !max_length.is_zero())
+            .ok_or(ConfigError::new(&format!("{}: max_length must be positive, got {}", name, options.max_length)))?;
+        Ok(Arc::new(NoteGuard::new(name, max_length)))
+    }
+}
+
+impl NodeFactory for Arpeggiator {
+    type Options = ArpeggiatorOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: ArpeggiatorOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let seconds = |field: &str, secs: Option<f32>| secs
+            .ok_or(ConfigError::new(&format!("{}: {} is required by this mode", name, field)))
+            .and_then(|secs| Duration::try_from_secs_f32(secs)
+                .map_err(|_| ConfigError::new(&format!("{}: {} must not be negative, got {}", name, field, secs))));
+        let style = match options.mode {
+            ChordMode::Strum => ChordStyle::Strum { spread: seconds("spread", options.spread)? },
+            ChordMode::Arpeggiate => {
+                let rate = seconds("rate", options.rate)?;
+                if rate.is_zero() {
+                    return Err(ConfigError::new(&format!("{}: rate must be positive", name)));
+                }
+                ChordStyle::Arpeggiate { rate }
+            }
+            ChordMode::Split => {
+                if options.channels.is_empty() || options.channels.iter().any(|channel| *channel > 15) {
+                    return Err(ConfigError::new(&format!("{}: channels must list at least one channel within 0-15", name)));
+                }
+                ChordStyle::Split { channels: options.channels }
+            }
+        };
+        let window = seconds("window", Some(options.window))?;
+        Ok(Arc::new(Arpeggiator::new(name, window, options.pattern, style)))
+    }
+}
+
+impl NodeFactory for Quantise {
+    type Options = QuantiseOptions;
+
+    fn factory(_ctx: &Config, name: &str, options: QuantiseOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        if options.grid.is_none() && options.humanise.is_none() {
+            return Err(ConfigError::new(&format!("{}: at least one of grid and humanise is required", name)));
+        }
+        if options.tempo.is_some_and(|tempo| !(tempo.is_finite() && tempo > 0.0)) {
+            return Err(ConfigError::new(&format!("{}: tempo must be positive", name)));
+        }
+        if options.grid.is_some_and(|grid| !(grid.is_finite() && grid > 0.0)) {
+            return Err(ConfigError::new(&format!("{}: grid must be positive", name)));
+        }
+        let min_tempo = options.min_tempo.or(options.tempo);
+        if options.grid.is_some() && min_tempo.is_none() {
+            return Err(ConfigError::new(&format!("{}: min_tempo is required by a grid without a tempo", name)));
+        }
+        if options.min_tempo.is_some_and(|min_tempo| !(min_tempo.is_finite() && min_tempo > 0.0)) {
+            return Err(ConfigError::new(&format!("{}: min_tempo must be positive", name)));
+        }
+        if options.tempo.zip(options.min_tempo).is_some_and(|(tempo, min_tempo)| min_tempo > tempo) {
+            return Err(ConfigError::new(&format!("{}: min_tempo must not exceed tempo", name)));
+        }
+        if !(0.0..1.0).contains(&options.swing) {
+            return Err(ConfigError::new(&format!("{}: swing must be within 0-1, got {}", name, options.swing)));
+        }
+        let humanise = options.humanise.map(|humanise| {
+            let timing = Duration::try_from_secs_f32(humanise.timing)
+                .map_err(|_| ConfigError::new(&format!("{}: humanise timing must not be negative", name)))?;
+            Ok(Humanise { timing, velocity: humanise.velocity, seed: humanise.seed })
+        }).transpose()?;
+        let grid = options.grid.map(|step| Grid { step: step as f64, swing: options.swing as f64 });
+        Ok(Arc::new(Quantise::new(name, options.tempo.map(f64::from), min_tempo.map(f64::from), grid, humanise)))
+    }
+}
+
+impl NodeFactory for Clock {
+    type Options = ClockOptions;
+
+    // the latency of each Output is resolved once the whole graph is built
+    fn factory(_ctx: &Config, name: &str, options: ClockOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let mut outputs: Vec<(String, f32)> = Vec::new();
+        for output in options.outputs {
+            if outputs.iter().any(|(to, _offset)| *to == output.to) {
+                return Err(ConfigError::new(&format!("{}: {} is given more than once", name, output.to)));
+            }
+            if Duration::try_from_secs_f32(output.offset.abs()).is_err() {
+                return Err(ConfigError::new(&format!("{}: offset for {} must be finite", name, output.to)));
+            }
+            outputs.push((output.to, output.offset));
+        }
+        Ok(Arc::new(Clock::new(name, outputs, options.forward)))
     }
 }
 
+#[cfg(feature = "python")]
 impl NodeFactory for PyNode {
-    fn factory(_ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
-        let duration = Duration::from_secs_f32(
-            config.duration.ok_or(ConfigError::new("Duration missing"))?
-        );
-        let source_path = config.source.as_ref().ok_or(ConfigError::new("Source missing"))?;
-        let source = read_to_string(Path::new(source_path)).map_err(ConfigError::of)?;
-        let pynode = PyNode::new(source.as_str(), duration).map_err(ConfigError::of)?;
-        Ok(Arc::new(pynode))
+    type Options = PyNodeOptions;
+
+    fn factory(ctx: &Config, name: &str, options: PyNodeOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let seconds = |field: &str, secs: f32| Duration::try_from_secs_f32(secs)
+            .map_err(|_| ConfigError::new(&format!("{}: {} must be finite and not negative, got {}", name, field, secs)));
+        let duration = seconds("duration", options.duration)?;
+        let timeout = options.timeout.map(|timeout| seconds("timeout", timeout)).transpose()?;
+        if ctx.offline() {
+            return Ok(Arc::new(DelayNode::new(duration)));
+        }
+        let pynode = Arc::new(PyNode::new(
+            name,
+            Path::new(&options.source),
+            duration,
+            options.class.as_deref(),
+            options.params.as_ref(),
+            Execution {
+                timeout: timeout.unwrap_or(duration),
+                on_timeout: options.on_timeout,
+                workers: options.workers
+            }
+        ).map_err(ConfigError::of)?);
+        PyNode::watch(&pynode);
+        Ok(pynode)
+    }
+}
+
+#[cfg(feature = "rhai")]
+impl NodeFactory for RhaiNode {
+    type Options = RhaiNodeOptions;
+
+    fn factory(ctx: &Config, name: &str, options: RhaiNodeOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let duration = Duration::try_from_secs_f32(options.duration)
+            .map_err(|_| ConfigError::new(&format!("{}: duration must be finite and not negative, got {}", name, options.duration)))?;
+        if ctx.offline() {
+            return Ok(Arc::new(DelayNode::new(duration)));
+        }
+        let source = read_to_string(&options.source).map_err(ConfigError::of)?;
+        let node = RhaiNode::new(
+            name,
+            source.as_str(),
+            duration,
+            options.params.as_ref(),
+            options.max_operations
+        ).map_err(ConfigError::of)?;
+        Ok(Arc::new(node))
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl NodeFactory for WasmNode {
+    type Options = WasmNodeOptions;
+
+    fn factory(ctx: &Config, name: &str, options: WasmNodeOptions) -> Result<Arc<dyn Node>, ConfigError> {
+        let duration = Duration::try_from_secs_f32(options.duration)
+            .map_err(|_| ConfigError::new(&format!("{}: duration must be finite and not negative, got {}", name, options.duration)))?;
+        if ctx.offline() {
+            return Ok(Arc::new(DelayNode::new(duration)));
+        }
+        // modules may be given in either the binary or text format
+        let wasm = wat::parse_file(&options.source).map_err(ConfigError::of)?;
+        let node = WasmNode::new(
+            name,
+            wasm.as_slice(),
+            duration,
+            options.params.as_ref(),
+            options.fuel
+        ).map_err(ConfigError::of)?;
+        Ok(Arc::new(node))
     }
 }
 
 // -----------------------
 type FactoryFunction = fn(&Config, &NodeConfig) -> Result<Arc<dyn Node>, ConfigError>;
 
-pub(super) trait NodeFactory {
-    fn factory(ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError>;
-}
\ No newline at end of file
+// node types are constructed from their own options, deserialised from the node's config alongside its name
+pub trait NodeFactory {
+    type Options: DeserializeOwned;
+
+    fn factory(ctx: &Config, name: &str, options: Self::Options) -> Result<Arc<dyn Node>, ConfigError>;
+}
+
+fn build<T: NodeFactory>(ctx: &Config, config: &NodeConfig) -> Result<Arc<dyn Node>, ConfigError> {
+    // errors are reported against the path of the offending field, e.g. `drums.arms[0].drums[1].inputs`
+    let options = serde_path_to_error::deserialize(Value::Mapping(config.options.clone())).map_err(|err| {
+        let path = err.path().to_string();
+        if path == "." {
+            ConfigError::new(&format!("{}: {}", config.name, err.inner()))
+        } else {
+            ConfigError::new(&format!("{}.{}: {}", config.name, path, err.inner()))
+        }
+    })?;
+    T::factory(ctx, config.name.as_str(), options)
+}
+
+// registers a node type under the given name, replacing any existing type of the same name
+pub fn register<T: NodeFactory>(type_name: &str) {
+    TYPES.write().unwrap().insert(String::from(type_name), build::<T>);
+}
+
+pub(super) fn factory(type_name: &str) -> Option<FactoryFunction> {
+    TYPES.read().unwrap().get(type_name).copied()
+}
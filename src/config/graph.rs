@@ -1,30 +1,244 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::fs::metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use log::{error, info};
+use once_cell::sync::Lazy;
 
-use crate::config::config::{Config, ConfigError};
+use crate::config::config::{Config, ConfigError, Fingerprint, Upstream};
+use crate::control::Gate;
 use crate::node::Node;
 
-pub(crate) struct Graph {
-    nodes: HashMap<String, Arc<dyn Node>>
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+// the reference point for graph time, initialised once the first graph is built
+pub(crate) static EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+mod arrivals;
+mod dot;
+
+pub use arrivals::Arrivals;
+
+pub(crate) struct NodeInfo<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) type_: &'a str,
+    pub(crate) next: Option<&'a str>,
+    pub(crate) targets: &'a [String],
+    pub(crate) node: &'a Arc<Gate>
+}
+
+pub struct Graph {
+    nodes: HashMap<String, (Arc<Gate>, Fingerprint)>,
+    // the successor of each bound node
+    edges: HashMap<String, String>,
+    // any further nodes routed to by each node
+    routes: HashMap<String, Vec<String>>,
+    sources: Vec<PathBuf>
 }
 
 impl Graph {
-    pub(crate) fn from_yaml(yaml: &str) -> Result<Self, ConfigError> {
-        serde_yml::from_str::<Config>(yaml).map_err(ConfigError::of)?.build()
+    // includes are resolved relative to the working directory
+    pub fn from_yaml(yaml: &str) -> Result<Self, ConfigError> {
+        Config::from_yaml(yaml, Path::new("."))?.build()
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Config::from_file(path)?.build()
+    }
+
+    // builds the graph without opening any ports or starting any scripts, such that it may be described but not run
+    pub fn from_file_offline(path: &Path) -> Result<Self, ConfigError> {
+        Config::from_file(path)?.build_offline()
+    }
+
+    // rebuilds the graph from the given config file, keeping unchanged nodes running. On error the graph is unchanged
+    pub fn reload(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let graph = Config::from_file(path)?.rebuild(Some(self))?;
+        let kept = graph.nodes.iter()
+            .filter(|(name, (node, _))| self.nodes.get(*name).is_some_and(|(prev, _)| Arc::ptr_eq(node, prev)))
+            .count();
+        info!(
+            target: "Config",
+            "Reloaded {}: kept {} nodes, built {}, removed {}",
+            path.display(),
+            kept,
+            graph.nodes.len() - kept,
+            self.nodes.keys().filter(|name| !graph.nodes.contains_key(*name)).count()
+        );
+        *self = graph;
+        Ok(())
+    }
+
+    // polls the files the graph was built from, reloading it whenever they change. Never returns
+    pub fn watch(graph: &RwLock<Graph>, path: &Path) -> ! {
+        let modified_sources = || -> Vec<_> {
+            graph.read().unwrap().sources.iter().map(|source| modified(source)).collect()
+        };
+        let mut last_modified = modified_sources();
+        loop {
+            thread::sleep(RELOAD_INTERVAL);
+            if modified_sources() == last_modified {
+                continue;
+            }
+            if let Err(err) = graph.write().unwrap().reload(path) {
+                error!(target: "Config", "Keeping the previous graph, as the config failed to load: {}", err);
+            }
+            // a reload may add or remove includes, and failed reloads are not retried until the files change again
+            last_modified = modified_sources();
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Arc<Gate>> {
+        self.nodes.get(name).map(|(node, _)| node)
+    }
+
+    // every node, ordered by name
+    pub(crate) fn describe(&self) -> Vec<NodeInfo<'_>> {
+        let mut nodes: Vec<_> = self.nodes.iter()
+            .map(|(name, (node, fingerprint))| NodeInfo {
+                name: name.as_str(),
+                type_: fingerprint.type_name(),
+                next: self.edges.get(name).map(String::as_str),
+                targets: self.routes.get(name).map(Vec::as_slice).unwrap_or_default(),
+                node
+            })
+            .collect();
+        nodes.sort_unstable_by_key(|info| info.name);
+        nodes
+    }
+
+    // passes the arrivals of the complete graph to each of its nodes, such that they may compensate for them
+    pub(crate) fn resolve(&self) {
+        let arrivals = Arrivals::new(self);
+        for (name, (node, _)) in self.nodes.iter() {
+            node.resolve(name, &arrivals);
+        }
+    }
+
+    fn delay(&self, name: &str) -> Duration {
+        self.nodes.get(name).map(|(node, _)| node.delay()).unwrap_or_default()
+    }
+
+    // every edge of the graph, whether to a node's successor or one of its targets
+    fn edges(&self) -> impl Iterator<Item = (&str, &str)> {
+        let edges = self.edges.iter().map(|(from, to)| (from.as_str(), to.as_str()));
+        let routes = self.routes.iter().flat_map(|(from, targets)| {
+            targets.iter().map(move |to| (from.as_str(), to.as_str()))
+        });
+        edges.chain(routes)
+    }
+
+    pub(super) fn new(sources: Vec<PathBuf>) -> Self {
+        Lazy::force(&EPOCH);
+        Graph { nodes: HashMap::new(), edges: HashMap::new(), routes: HashMap::new(), sources }
+    }
+
+    // the node of the given name, should it have been built from the same config, alongside the delays it depends on
+    pub(super) fn reuse(
+        &self,
+        name: &str,
+        fingerprint: &Fingerprint,
+        upstream: impl FnOnce(&Upstream) -> Upstream
+    ) -> Option<(Arc<Gate>, Upstream)> {
+        self.nodes.get(name)
+            .filter(|(_node, prev)| prev.matches(fingerprint, upstream))
+            .map(|(node, prev)| (node.clone(), prev.upstream().clone()))
     }
 
-    pub(super) fn new() -> Self {
-        Graph { nodes: HashMap::new() }
+    pub(super) fn contains(&self, name: &str) -> bool {
+        self.nodes.contains_key(name)
     }
 
-    pub(super) fn bind(&self, from: &str, to: &str) -> Result<(), ConfigError> {
-        if let (Some(from), Some(to)) = (self.nodes.get(from), self.nodes.get(to)) {
-            return Ok(from.bind(Arc::downgrade(to)))
+    pub(super) fn bind(&mut self, from: &str, to: &str) -> Result<(), ConfigError> {
+        if let (Some((from_node, _)), Some((to_node, _))) = (self.nodes.get(from), self.nodes.get(to)) {
+            let to_node: Arc<dyn Node> = to_node.inlet(from).unwrap_or_else(|| to_node.clone());
+            from_node.bind(Arc::downgrade(&to_node));
+            self.edges.insert(String::from(from), String::from(to));
+            return Ok(())
         }
         Err(ConfigError::new(&format!("couldn't locate node: {}", to)))
     }
 
-    pub(super) fn insert(&mut self, name: &str, node: Arc<dyn Node>) {
-        self.nodes.insert(String::from(name), node);
+    pub(super) fn bind_target(&mut self, from: &str, to: &str) -> Result<(), ConfigError> {
+        if let (Some((from_node, _)), Some((to_node, _))) = (self.nodes.get(from), self.nodes.get(to)) {
+            let to_node: Arc<dyn Node> = to_node.inlet(from).unwrap_or_else(|| to_node.clone());
+            from_node.bind_target(to, Arc::downgrade(&to_node));
+            self.routes.entry(String::from(from)).or_default().push(String::from(to));
+            return Ok(())
+        }
+        Err(ConfigError::new(&format!("couldn't locate node: {}", to)))
+    }
+
+    pub(super) fn insert(&mut self, name: &str, node: Arc<Gate>, fingerprint: Fingerprint) {
+        self.nodes.insert(String::from(name), (node, fingerprint));
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+    use super::*;
+
+    fn config(test: &str, yaml: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mechsync-{}-{}", test, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        write(&path, yaml).unwrap();
+        path
+    }
+
+    fn node(graph: &Graph, name: &str) -> Arc<dyn Node> {
+        graph.nodes.get(name).unwrap().0.clone()
+    }
+
+    const CHAIN: &str = "
+- name: In
+  type: DebugNode
+  next: Delay
+- name: Delay
+  type: DelayNode
+  duration: 0.1
+  next: Total
+- name: Total
+  type: DelayNode
+  duration: 0.5
+  is_total: true
+  next: Out
+- name: Out
+  type: DebugNode
+";
+
+    #[test]
+    fn reloads_keep_unchanged_nodes() {
+        let path = config("reload-kept", CHAIN);
+        let mut graph = Graph::from_file(&path).unwrap();
+        let (input, delay, total, output) = (node(&graph, "In"), node(&graph, "Delay"), node(&graph, "Total"), node(&graph, "Out"));
+
+        write(&path, CHAIN.replace("duration: 0.1", "duration: 0.2")).unwrap();
+        graph.reload(&path).unwrap();
+        assert!(Arc::ptr_eq(&input, &node(&graph, "In")));
+        assert!(!Arc::ptr_eq(&delay, &node(&graph, "Delay")));
+        // the total delay depends on the delay before it, whereas the output doesn't
+        assert!(!Arc::ptr_eq(&total, &node(&graph, "Total")));
+        assert_eq!(node(&graph, "Total").delay(), Duration::from_secs_f32(0.5) - Duration::from_secs_f32(0.2));
+        assert!(Arc::ptr_eq(&output, &node(&graph, "Out")));
+    }
+
+    #[test]
+    fn invalid_reloads_keep_the_previous_graph() {
+        let path = config("reload-invalid", CHAIN);
+        let mut graph = Graph::from_file(&path).unwrap();
+        let input = node(&graph, "In");
+
+        write(&path, CHAIN.replace("next: Out", "next: Missing")).unwrap();
+        assert!(graph.reload(&path).is_err());
+        assert!(Arc::ptr_eq(&input, &node(&graph, "In")));
+        assert_eq!(graph.nodes.len(), 4);
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::config::graph::Graph;
+
+// the cumulative delay of messages as they arrive at every node of a complete graph, along each of its predecessors.
+// Passed to every node through Node::resolve once the graph is built, and again whenever delays change at runtime
+#[derive(Default)]
+pub struct Arrivals {
+    // along the slowest predecessor
+    delays: HashMap<String, Duration>,
+    arrivals: HashMap<String, Vec<(String, Duration)>>
+}
+
+impl Arrivals {
+    pub(super) fn new(graph: &Graph) -> Self {
+        let mut predecessors: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (from, to) in graph.edges() {
+            predecessors.entry(to).or_default().push(from);
+        }
+        let mut resolver = Resolver { graph, predecessors, memo: HashMap::new(), arrivals: Arrivals::default() };
+        for name in graph.nodes.keys() {
+            resolver.delay(name);
+        }
+        resolver.arrivals
+    }
+
+    // the cumulative delay of every node leading up to the given node, along its slowest predecessor
+    pub fn delay(&self, name: &str) -> Duration {
+        self.delays.get(name).copied().unwrap_or_default()
+    }
+
+    // the cumulative delay of each of the given node's predecessors as their messages arrive, by name
+    pub fn get(&self, name: &str) -> &[(String, Duration)] {
+        self.arrivals.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+struct Resolver<'a> {
+    graph: &'a Graph,
+    predecessors: HashMap<&'a str, Vec<&'a str>>,
+    memo: HashMap<&'a str, Option<Duration>>,
+    arrivals: Arrivals
+}
+
+impl<'a> Resolver<'a> {
+    // nodes within cycles have no delay, as it would be unbounded, so the edge closing a cycle has no arrival. A node's
+    // own delay is used rather than its delay to each successor, as a Clock's delay to its outputs follows from their
+    // arrivals rather than adding to them
+    fn delay(&mut self, name: &'a str) -> Option<Duration> {
+        if let Some(delay) = self.memo.get(name) {
+            return *delay;
+        }
+        self.memo.insert(name, None);
+        let mut arrivals = Vec::new();
+        for prev in self.predecessors.get(name).cloned().unwrap_or_default() {
+            if let Some(delay) = self.delay(prev) {
+                arrivals.push((String::from(prev), delay.saturating_add(self.graph.delay(prev))));
+            }
+        }
+        let delay = arrivals.iter().map(|(_prev, arrival)| *arrival).max().unwrap_or_default();
+        self.arrivals.delays.insert(String::from(name), delay);
+        self.arrivals.arrivals.insert(String::from(name), arrivals);
+        self.memo.insert(name, Some(delay));
+        Some(delay)
+    }
+}
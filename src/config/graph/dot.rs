@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::time::Duration;
+use crate::config::graph::{Arrivals, Graph};
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000f64)
+}
+
+// the predecessor along which the slowest path reaches the node
+fn slowest<'a>(arrivals: &'a Arrivals, name: &str) -> Option<&'a str> {
+    arrivals.get(name).iter()
+        .find(|(_prev, arrival)| *arrival == arrivals.delay(name))
+        .map(|(prev, _arrival)| prev.as_str())
+}
+
+impl Graph {
+    // renders the graph in Graphviz DOT, labelling edges with the latency at which messages arrive along the slowest
+    // path from a source (typically Inputs), and highlighting the slowest path into each sink (typically Outputs)
+    pub fn to_dot(&self) -> String {
+        let names: BTreeMap<&str, &str> = self.nodes.iter()
+            .map(|(name, (_node, fingerprint))| (name.as_str(), fingerprint.type_name()))
+            .collect();
+        let arrivals = Arrivals::new(self);
+
+        let mut highlighted = HashSet::new();
+        let sinks: Vec<&str> = names.keys()
+            .filter(|name| !self.edges.contains_key(**name) && !self.routes.contains_key(**name))
+            .copied()
+            .collect();
+        for name in sinks {
+            let mut current = name;
+            let mut visited = HashSet::from([current]);
+            while let Some(prev) = slowest(&arrivals, current) {
+                highlighted.insert((prev, current));
+                if !visited.insert(prev) {
+                    break;
+                }
+                current = prev;
+            }
+        }
+
+        let mut dot = String::from("digraph MechSync {\n    rankdir=LR;\n    node [shape=box];\n\n");
+        for (name, type_) in names.iter() {
+            let _ = writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\\n{}\\ndelay {}\"];",
+                escape(name), escape(name), escape(type_), millis(self.delay(name))
+            );
+        }
+        dot.push('\n');
+        let mut edges: Vec<(&str, &str)> = self.edges().collect();
+        edges.sort_unstable();
+        for (name, next) in edges {
+            let latency = match arrivals.get(next).iter().find(|(prev, _arrival)| prev == name) {
+                Some((_prev, arrival)) => millis(*arrival),
+                None => String::from("cycle")
+            };
+            let style = if highlighted.contains(&(name, next)) { ", color=red, penwidth=2" } else { "" };
+            let _ = writeln!(dot, "    \"{}\" -> \"{}\" [label=\"{}\"{}];", escape(name), escape(next), latency, style);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::config::config::Config;
+
+    #[test]
+    fn slowest_paths_are_highlighted() {
+        // built offline, so the Inputs and Output open no ports
+        let graph = Config::from_yaml("
+            - name: Keys
+              type: Input
+              next: Slow
+            - name: Pads
+              type: Input
+              next: Fast
+            - name: Slow
+              type: DelayNode
+              duration: 0.2
+              next: Out
+            - name: Fast
+              type: DelayNode
+              duration: 0.1
+              next: Out
+            - name: Out
+              type: Output
+        ", Path::new(".")).unwrap().build_offline().unwrap();
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"Slow\" [label=\"Slow\\nDelayNode\\ndelay 200.0ms\"];"));
+        assert!(dot.contains("\"Keys\" -> \"Slow\" [label=\"0.0ms\", color=red, penwidth=2];"));
+        assert!(dot.contains("\"Slow\" -> \"Out\" [label=\"200.0ms\", color=red, penwidth=2];"));
+        assert!(dot.contains("\"Pads\" -> \"Fast\" [label=\"0.0ms\"];"));
+        assert!(dot.contains("\"Fast\" -> \"Out\" [label=\"100.0ms\"];"));
+    }
+
+    #[test]
+    fn cycles_are_labelled() {
+        let graph = Config::from_yaml("
+            - name: A
+              type: DelayNode
+              duration: 0.1
+              next: B
+            - name: B
+              type: DebugNode
+              next: A
+        ", Path::new(".")).unwrap().build_offline().unwrap();
+        let dot = graph.to_dot();
+        assert_eq!(dot.matches("[label=\"cycle\"").count(), 1);
+    }
+}
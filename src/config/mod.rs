@@ -1,5 +1,9 @@
 pub(crate) mod graph;
+#[allow(clippy::module_inception)]
 mod config;
 mod factories;
 
-pub(crate) use config::ArmsConfig;
\ No newline at end of file
+pub use config::{Config, ConfigError};
+pub use factories::{register, NodeFactory};
+pub use graph::{Arrivals, Graph};
+pub(crate) use config::{MessageMatch, StringMovement, VelocityCurve};
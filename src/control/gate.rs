@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use may::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::config::Arrivals;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Mode {
+    Active,
+    // drops everything sent to or by the node
+    Muted,
+    // passes messages straight to the node's successor
+    Bypassed
+}
+
+impl Mode {
+    fn from_u8(mode: u8) -> Mode {
+        match mode {
+            1 => Mode::Muted,
+            2 => Mode::Bypassed,
+            _ => Mode::Active
+        }
+    }
+}
+
+// receives the output of the gated node, so that nodes without predecessors (such as Inputs) may still be muted
+struct Outlet {
+    mode: Arc<AtomicU8>,
+    next: OptNode
+}
+
+impl Node for Outlet {
+    fn call(&self, data: MidiData) {
+        if Mode::from_u8(self.mode.load(Ordering::Relaxed)) != Mode::Muted {
+            self.next.call(data);
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}
+
+// wraps every node of a graph, allowing it to be muted or bypassed at runtime
+pub(crate) struct Gate {
+    node: Arc<dyn Node>,
+    mode: Arc<AtomicU8>,
+    outlet: Arc<Outlet>,
+    // outlets for each of the node's targets besides `next`
+    targets: Mutex<HashMap<String, Arc<Outlet>>>
+}
+
+impl Gate {
+    pub(crate) fn new(node: Arc<dyn Node>) -> Self {
+        let mode = Arc::new(AtomicU8::new(Mode::Active as u8));
+        Gate {
+            node,
+            outlet: Arc::new(Outlet { mode: mode.clone(), next: RwLock::new(None) }),
+            targets: Mutex::new(HashMap::new()),
+            mode
+        }
+    }
+
+    pub(crate) fn node(&self) -> &Arc<dyn Node> {
+        &self.node
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        Mode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_mode(&self, mode: Mode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+}
+
+impl Node for Gate {
+    fn call(&self, data: MidiData) {
+        match self.mode() {
+            Mode::Active => self.node.call(data),
+            Mode::Muted => {}
+            Mode::Bypassed => self.outlet.call(data)
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.outlet.bind(node);
+        let outlet: Arc<dyn Node> = self.outlet.clone();
+        self.node.bind(Arc::downgrade(&outlet));
+    }
+
+    // bypassed nodes add no delay, so aren't compensated for
+    fn delay(&self) -> Duration {
+        match self.mode() {
+            Mode::Bypassed => Duration::ZERO,
+            _ => self.node.delay()
+        }
+    }
+
+    fn resolve(&self, name: &str, arrivals: &Arrivals) {
+        self.node.resolve(name, arrivals);
+    }
+
+    fn targets(&self) -> Vec<String> {
+        self.node.targets()
+    }
+
+    fn bind_target(&self, target: &str, node: Weak<dyn Node>) {
+        let outlet = self.targets.lock().unwrap()
+            .entry(String::from(target))
+            .or_insert_with(|| Arc::new(Outlet { mode: self.mode.clone(), next: RwLock::new(None) }))
+            .clone();
+        outlet.bind(node);
+        let outlet: Arc<dyn Node> = outlet;
+        self.node.bind_target(target, Arc::downgrade(&outlet));
+    }
+
+    fn delay_to(&self, target: &str) -> Duration {
+        match self.mode() {
+            Mode::Bypassed => Duration::ZERO,
+            _ => self.node.delay_to(target)
+        }
+    }
+
+    fn inlet(&self, from: &str) -> Option<Arc<dyn Node>> {
+        self.node.inlet(from)
+    }
+
+    fn state(&self) -> Value {
+        self.node.state()
+    }
+
+    fn set(&self, param: &str, value: &Value) -> Result<(), String> {
+        self.node.set(param, value)
+    }
+}
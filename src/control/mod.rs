@@ -0,0 +1,258 @@
+use std::fs::{remove_file, symlink_metadata};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use clap::Subcommand;
+use log::{error, info, trace};
+use may::go;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::config::Graph;
+use crate::data::{MidiData, ALL_NOTES_OFF, CONTROL_CHANGE, NOTE_OFF, SYSTEM};
+use crate::node::Node;
+
+mod gate;
+
+pub(crate) use gate::{Gate, Mode};
+
+// requests are sent as a single line of JSON tagged by `command`, e.g. {"command": "mute", "node": "MechBass"},
+// and are answered by a single line of either {"ok": result} or {"error": message}
+#[derive(Subcommand, Serialize, Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub(crate) enum Request {
+    /// List every node alongside its state
+    List,
+    /// Change a parameter of a node, such as a DelayNode's duration
+    Set {
+        node: String,
+        param: String,
+        #[arg(value_parser = parse_value)]
+        value: Value
+    },
+    /// Drop everything sent to or by a node
+    Mute { node: String },
+    /// Pass messages straight to a node's successor
+    Bypass { node: String },
+    /// Undo a mute or bypass
+    Resume { node: String },
+    /// Send all-notes-off on every channel of every Output
+    Panic,
+    /// Send a message into a node
+    Inject {
+        node: String,
+        instruction: u8,
+        channel: u8,
+        note: u8,
+        velocity: u8
+    }
+}
+
+// arguments which aren't valid JSON are taken as strings
+fn parse_value(arg: &str) -> Result<Value, String> {
+    Ok(serde_json::from_str(arg).unwrap_or_else(|_| Value::String(String::from(arg))))
+}
+
+fn find<'a>(graph: &'a Graph, name: &str) -> Result<&'a Arc<Gate>, String> {
+    graph.get(name).ok_or(format!("Unknown node: {}", name))
+}
+
+fn handle(graph: &RwLock<Graph>, request: Request) -> Result<Value, String> {
+    let graph = graph.read().unwrap();
+    match request {
+        Request::List => Ok(Value::Array(graph.describe().into_iter().map(|info| json!({
+            "name": info.name,
+            "type": info.type_,
+            "next": info.next,
+            "targets": info.targets,
+            "delay": info.node.delay().as_secs_f64(),
+            "mode": info.node.mode(),
+            "state": info.node.state()
+        })).collect())),
+        Request::Set { node, param, value } => {
+            find(&graph, &node)?.set(&param, &value)?;
+            graph.resolve();
+            Ok(Value::Null)
+        }
+        Request::Mute { node } => {
+            find(&graph, &node)?.set_mode(Mode::Muted);
+            graph.resolve();
+            Ok(Value::Null)
+        }
+        Request::Bypass { node } => {
+            find(&graph, &node)?.set_mode(Mode::Bypassed);
+            graph.resolve();
+            Ok(Value::Null)
+        }
+        Request::Resume { node } => {
+            find(&graph, &node)?.set_mode(Mode::Active);
+            graph.resolve();
+            Ok(Value::Null)
+        }
+        Request::Panic => {
+            // sent directly to the outputs, regardless of whether they're muted
+            for info in graph.describe().into_iter().filter(|info| info.type_ == "Output") {
+                for channel in 0..16 {
+                    info.node.node().call(MidiData { instruction: CONTROL_CHANGE, channel, note: ALL_NOTES_OFF, velocity: 0 });
+                }
+            }
+            Ok(Value::Null)
+        }
+        Request::Inject { node, instruction, channel, note, velocity } => {
+            if !(NOTE_OFF..=SYSTEM).contains(&instruction) {
+                return Err(format!("Instruction must be within 0x8-0xF, got {:#x}", instruction));
+            }
+            if channel > 15 {
+                return Err(format!("Channel must be within 0-15, got {}", channel));
+            }
+            if note > 127 || velocity > 127 {
+                return Err(format!("Note and velocity must be within 0-127, got {} and {}", note, velocity));
+            }
+            let node = find(&graph, &node)?.clone();
+            let data = MidiData { instruction, channel, note, velocity };
+            go!(move || node.call(data));
+            Ok(Value::Null)
+        }
+    }
+}
+
+fn connection(graph: &RwLock<Graph>, stream: UnixStream) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        trace!(target: "Control", "Received {}", line);
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(graph, request),
+            Err(err) => Err(err.to_string())
+        };
+        let response = match response {
+            Ok(result) => json!({ "ok": result }),
+            Err(message) => json!({ "error": message })
+        };
+        writeln!(writer, "{}", response)?;
+    }
+    Ok(())
+}
+
+// serves the control API on a Unix socket, replacing any stale socket left behind at the path. Anything else at the
+// path is left in place, failing to serve
+pub(crate) fn serve(graph: Arc<RwLock<Graph>>, path: &Path) -> std::io::Result<()> {
+    match symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => remove_file(path)?,
+        Ok(_) => return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists and isn't a socket", path.display())
+        )),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err)
+    }
+    let listener = UnixListener::bind(path)?;
+    info!(target: "Control", "Listening on {}", path.display());
+    thread::Builder::new().name(String::from("control")).spawn(move || {
+        for stream in listener.incoming() {
+            let graph = graph.clone();
+            let spawned = stream.and_then(|stream| thread::Builder::new()
+                .name(String::from("control connection"))
+                .spawn(move || {
+                    if let Err(err) = connection(&graph, stream) {
+                        error!(target: "Control", "Connection failed: {}", err);
+                    }
+                })
+            );
+            if let Err(err) = spawned {
+                error!(target: "Control", "Unable to accept connection: {}", err);
+            }
+        }
+    })?;
+    Ok(())
+}
+
+// sends a single request to a running instance, returning its response
+pub(crate) fn send(path: &Path, request: &Request) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::path::PathBuf;
+    use super::*;
+
+    fn graph() -> Arc<RwLock<Graph>> {
+        Arc::new(RwLock::new(Graph::from_yaml("
+            - name: In
+              type: DebugNode
+              next: Delay
+            - name: Delay
+              type: DelayNode
+              duration: 0.5
+              next: Out
+            - name: Out
+              type: DebugNode
+        ").unwrap()))
+    }
+
+    fn socket(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mechsync-{}-{}.sock", test, std::process::id()))
+    }
+
+    fn request(json: &str) -> Request {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn requests_are_answered_over_the_socket() {
+        let path = socket("control");
+        serve(graph(), &path).unwrap();
+
+        let set = request(r#"{"command": "set", "node": "Delay", "param": "duration", "value": 1.5}"#);
+        assert_eq!(send(&path, &set).unwrap(), json!({ "ok": null }));
+        assert_eq!(send(&path, &request(r#"{"command": "mute", "node": "Out"}"#)).unwrap(), json!({ "ok": null }));
+
+        let list = send(&path, &Request::List).unwrap();
+        let nodes = list["ok"].as_array().unwrap();
+        assert_eq!(nodes.iter().map(|node| node["name"].as_str().unwrap()).collect::<Vec<_>>(), ["Delay", "In", "Out"]);
+        assert_eq!(nodes[0]["delay"], json!(1.5));
+        assert_eq!(nodes[2]["mode"], json!("muted"));
+
+        let unknown = send(&path, &request(r#"{"command": "bypass", "node": "Missing"}"#)).unwrap();
+        assert_eq!(unknown, json!({ "error": "Unknown node: Missing" }));
+    }
+
+    #[test]
+    fn injected_messages_are_validated() {
+        let graph = graph();
+        let inject = |instruction, channel, note| handle(&graph, Request::Inject {
+            node: String::from("In"), instruction, channel, note, velocity: 100
+        });
+        assert!(inject(NOTE_OFF, 0, 40).is_ok());
+        assert!(inject(0x3, 0, 40).unwrap_err().contains("Instruction"));
+        assert!(inject(NOTE_OFF, 16, 40).unwrap_err().contains("Channel"));
+        assert!(inject(NOTE_OFF, 0, 128).unwrap_err().contains("Note"));
+    }
+
+    #[test]
+    fn only_stale_sockets_are_replaced() {
+        let path = socket("occupied");
+        write(&path, "not a socket").unwrap();
+        let err = serve(graph(), &path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(path.is_file());
+
+        // a socket left behind by a previous instance is replaced
+        let path = socket("stale");
+        let _ = remove_file(&path);
+        drop(UnixListener::bind(&path).unwrap());
+        serve(graph(), &path).unwrap();
+        assert!(send(&path, &Request::List).is_ok());
+    }
+}
@@ -1,5 +1,29 @@
-#[derive(Debug)]
-pub(crate) struct MidiData {
+pub const NOTE_OFF: u8 = 0b1000;
+pub const NOTE_ON: u8 = 0b1001;
+pub const POLY_PRESSURE: u8 = 0b1010;
+pub const CONTROL_CHANGE: u8 = 0b1011;
+pub const PROGRAM_CHANGE: u8 = 0b1100;
+pub const CHANNEL_PRESSURE: u8 = 0b1101;
+pub const PITCH_BEND: u8 = 0b1110;
+// system messages, whose low nibble (parsed as the channel) identifies the message
+pub const SYSTEM: u8 = 0b1111;
+
+// channel mode messages, sent as control changes
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+// system common messages
+pub const SONG_POSITION: u8 = 0x2;
+pub const SONG_SELECT: u8 = 0x3;
+
+// system real-time messages
+pub const TIMING_CLOCK: u8 = 0x8;
+pub const START: u8 = 0xA;
+pub const CONTINUE: u8 = 0xB;
+pub const STOP: u8 = 0xC;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiData {
     pub instruction: u8,
     pub channel: u8,
     pub note: u8,
@@ -7,8 +31,8 @@ pub(crate) struct MidiData {
 }
 
 impl MidiData {
-    pub(crate) fn from_slice(data: &[u8]) -> MidiData {
-        let (instruction, channel) = if let Some(inst) = data.get(0) {
+    pub fn from_slice(data: &[u8]) -> MidiData {
+        let (instruction, channel) = if let Some(inst) = data.first() {
             ((*inst & 0b1111_0000) >> 4, *inst & 0b0000_1111)
         } else {
             (0u8, 0u8)
@@ -23,7 +47,41 @@ impl MidiData {
         }
     }
 
-    pub(crate) fn to_array(&self) -> [u8; 3] {
+    // note-ons with zero velocity are treated as note-offs, as per the MIDI spec
+    pub fn is_note_on(&self) -> bool {
+        self.instruction == NOTE_ON && self.velocity != 0
+    }
+
+    pub fn is_note_off(&self) -> bool {
+        self.instruction == NOTE_OFF || (self.instruction == NOTE_ON && self.velocity == 0)
+    }
+
+    // whether the message refers to a note, including polyphonic pressure
+    pub fn is_note(&self) -> bool {
+        matches!(self.instruction, NOTE_OFF | NOTE_ON | POLY_PRESSURE)
+    }
+
+    pub fn is_system(&self, message: u8) -> bool {
+        self.instruction == SYSTEM && self.channel == message
+    }
+
+    // clock and transport, which follow the song rather than any one channel
+    pub fn is_transport(&self) -> bool {
+        self.instruction == SYSTEM && matches!(self.channel, SONG_POSITION | TIMING_CLOCK | START | CONTINUE | STOP)
+    }
+
+    // the number of bytes the message is sent as
+    pub fn size(&self) -> usize {
+        match (self.instruction, self.channel) {
+            (PROGRAM_CHANGE | CHANNEL_PRESSURE, _) => 2,
+            (SYSTEM, SONG_POSITION) => 3,
+            (SYSTEM, 0x1 | SONG_SELECT) => 2,
+            (SYSTEM, _) => 1,
+            _ => 3
+        }
+    }
+
+    pub fn to_array(&self) -> [u8; 3] {
         [
             (self.instruction << 4) | self.channel,
             self.note,
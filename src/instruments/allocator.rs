@@ -0,0 +1,130 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Instant;
+
+// anything able to play notes on behalf of an instrument, such as a drum arm, a voice, an actuator or a string
+pub(crate) trait Player {
+    fn plays(&self, note: u8) -> bool;
+
+    // whether the note could sound at the given time without cutting short another, or arriving before the player
+    fn free(&self, note: u8, at: Instant) -> bool;
+
+    // how far the player would move to play the note, in whichever unit the instrument measures movement by
+    fn cost(&self, note: u8) -> f32;
+
+    // when the player last started moving
+    fn last(&self) -> Option<Instant>;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Candidate {
+    pub(crate) index: usize,
+    pub(crate) free: bool,
+    pub(crate) cost: f32
+}
+
+// chooses which of an instrument's players plays each note
+pub(crate) struct Allocator<P: Player> {
+    players: Vec<P>
+}
+
+impl<P: Player> Allocator<P> {
+    pub(crate) fn new(players: Vec<P>) -> Self {
+        Allocator { players }
+    }
+
+    // every player able to play the note
+    pub(crate) fn candidates(&self, note: u8, at: Instant) -> Vec<Candidate> {
+        self.players.iter()
+            .enumerate()
+            .filter(|(_index, player)| player.plays(note))
+            .map(|(index, player)| Candidate { index, free: player.free(note, at), cost: player.cost(note) })
+            .collect()
+    }
+
+    // the player to play the note, preferring free players, then those which would move the least, and finally
+    // whichever has been idle for the longest. Busy players are only chosen should no others be able to play it
+    pub(crate) fn choose(&self, note: u8, at: Instant) -> Option<Candidate> {
+        self.candidates(note, at).into_iter().min_by(|a, b| b.free.cmp(&a.free)
+            .then(a.cost.total_cmp(&b.cost))
+            .then(self.players[a.index].last().cmp(&self.players[b.index].last()))
+        )
+    }
+}
+
+impl<P: Player> Deref for Allocator<P> {
+    type Target = [P];
+
+    fn deref(&self) -> &[P] {
+        &self.players
+    }
+}
+
+impl<P: Player> DerefMut for Allocator<P> {
+    fn deref_mut(&mut self) -> &mut [P] {
+        &mut self.players
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    struct Finger {
+        notes: (u8, u8),
+        position: u8,
+        busy: bool,
+        last: Option<Instant>
+    }
+
+    impl Player for Finger {
+        fn plays(&self, note: u8) -> bool {
+            (self.notes.0..=self.notes.1).contains(&note)
+        }
+
+        fn free(&self, _note: u8, _at: Instant) -> bool {
+            !self.busy
+        }
+
+        fn cost(&self, note: u8) -> f32 {
+            self.position.abs_diff(note) as f32
+        }
+
+        fn last(&self) -> Option<Instant> {
+            self.last
+        }
+    }
+
+    fn finger(notes: (u8, u8), position: u8, busy: bool, last: Option<Instant>) -> Finger {
+        Finger { notes, position, busy, last }
+    }
+
+    #[test]
+    fn free_players_are_preferred_then_the_closest() {
+        let allocator = Allocator::new(vec![
+            finger((40, 60), 50, true, None),
+            finger((40, 60), 40, false, None),
+            finger((40, 60), 44, false, None),
+            finger((50, 60), 50, false, None)
+        ]);
+        let chosen = allocator.choose(50, Instant::now()).unwrap();
+        assert_eq!((chosen.index, chosen.free), (3, true));
+        let chosen = allocator.choose(45, Instant::now()).unwrap();
+        assert_eq!((chosen.index, chosen.free), (2, true));
+        assert_eq!(allocator.candidates(45, Instant::now()).len(), 3);
+        assert!(allocator.choose(70, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn ties_go_to_the_longest_idle() {
+        let now = Instant::now();
+        let allocator = Allocator::new(vec![
+            finger((40, 60), 40, false, Some(now)),
+            finger((40, 60), 40, false, Some(now - Duration::from_secs(1)))
+        ]);
+        assert_eq!(allocator.choose(40, now).unwrap().index, 1);
+        // busy players are still chosen when no others are able to play the note
+        let allocator = Allocator::new(vec![finger((40, 60), 40, true, Some(now))]);
+        assert!(!allocator.choose(40, now).unwrap().free);
+    }
+}
@@ -1,57 +1,200 @@
 use std::sync::Weak;
 use std::time::{Duration, Instant};
 use log::{info, warn};
-use may::sync::RwLock;
-use crate::config::ArmsConfig;
-use crate::data::MidiData;
+use may::sync::{Mutex, RwLock};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use crate::config::VelocityCurve;
+use crate::data::{MidiData, NOTE_ON};
+use crate::instruments::allocator::{Allocator, Player};
 use crate::node::{Node, OptNode};
 
 const DRUMBOT_DELAY: Duration = Duration::from_millis(1970);
 const KICK_NOTE: u8 = 36;
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DrumBotOptions {
+    pub(crate) arms: Vec<ArmsConfig>
+}
+
+// Arms may either be given as a compact `input: output` map, or in a structured form naming the arm and its drums
+pub(crate) enum ArmsConfig {
+    Named {
+        name: String,
+        drums: Vec<DrumConfig>
+    },
+    Compact(Vec<(u8, u8)>)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NamedArmConfig {
+    name: String,
+    drums: Vec<DrumConfig>
+}
+
+// chooses the form up front rather than being untagged, so that errors within a structured arm aren't lost
+impl<'de> Deserialize<'de> for ArmsConfig {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let value = serde_yml::Value::deserialize(d)?;
+        let structured = value.as_mapping()
+            .map(|mapping| mapping.contains_key("drums") || mapping.contains_key("name"))
+            .unwrap_or(false);
+        if structured {
+            let arm: NamedArmConfig = serde_path_to_error::deserialize(value)
+                .map_err(|err| D::Error::custom(format!("{}: {}", err.path(), err.inner())))?;
+            return Ok(ArmsConfig::Named { name: arm.name, drums: arm.drums });
+        }
+        tuple_vec_map::deserialize(value)
+            .map(ArmsConfig::Compact)
+            .map_err(D::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DrumConfig {
+    pub(crate) name: String,
+    pub(crate) inputs: Vec<u8>,
+    pub(crate) output: u8,
+    // position of the drum along the arm's travel, used to prefer arms which are already nearby
+    #[serde(default)]
+    pub(crate) position: f32,
+    // seconds required for the arm to settle onto this drum
+    #[serde(default)]
+    pub(crate) travel_time: f32,
+    pub(crate) velocity: Option<VelocityCurve>
+}
+
+struct Drum {
+    name: String,
+    inputs: Vec<u8>,
+    output: u8,
+    position: f32,
+    travel_time: Duration,
+    velocity: Option<VelocityCurve>
+}
+
+impl Drum {
+    fn from_config(config: &DrumConfig) -> Self {
+        Drum {
+            name: config.name.clone(),
+            inputs: config.inputs.clone(),
+            output: config.output,
+            position: config.position,
+            travel_time: Duration::from_secs_f32(config.travel_time),
+            velocity: config.velocity.clone(),
+        }
+    }
+
+    fn velocity(&self, velocity: u8) -> u8 {
+        self.velocity.as_ref().map(|curve| curve.apply(velocity)).unwrap_or(velocity)
+    }
+}
+
 struct Arm {
-    mapping: Vec<(u8, u8)>, // likely cheaper to just use a vec
+    name: String,
+    drums: Vec<Drum>, // likely cheaper to just use a vec
     // with linear search instead of a hash
-    last_played: u8,
+    current: usize,
     ts: Instant
 }
 
 impl Arm {
-    fn new(mapping: Vec<(u8, u8)>) -> Arm {
-        let last_played = mapping.get(0).map(|e| e.0).unwrap_or_default();
+    fn new(index: usize, config: &ArmsConfig) -> Arm {
+        let (name, drums) = match config {
+            ArmsConfig::Named { name, drums } => (name.clone(), drums.iter().map(Drum::from_config).collect()),
+            ArmsConfig::Compact(mapping) => (format!("arm {}", index), Arm::compact_drums(mapping))
+        };
         Arm {
-            mapping,
-            last_played,
+            name,
+            drums,
+            current: 0,
             ts: Instant::now(),
         }
     }
 
-    fn get(&self, key: u8) -> Option<u8> {
-        self.mapping.iter()
-            .filter(|(k, _v)| *k == key)
-            .map(|(_k, v)| *v)
-            .next()
+    // compact mappings are grouped by output note, as every output corresponds to a single physical drum
+    fn compact_drums(mapping: &[(u8, u8)]) -> Vec<Drum> {
+        let mut drums: Vec<Drum> = Vec::new();
+        for &(input, output) in mapping {
+            if let Some(drum) = drums.iter_mut().find(|drum| drum.output == output) {
+                drum.inputs.push(input);
+                continue;
+            }
+            drums.push(Drum {
+                name: output.to_string(),
+                inputs: vec![input],
+                output,
+                position: 0f32,
+                travel_time: Duration::ZERO,
+                velocity: None,
+            });
+        }
+        drums
+    }
+
+    fn get(&self, key: u8) -> Option<usize> {
+        self.drums.iter().position(|drum| drum.inputs.contains(&key))
+    }
+
+    fn distance(&self, drum: usize) -> f32 {
+        let from = self.drums.get(self.current).map(|d| d.position).unwrap_or_default();
+        (self.drums[drum].position - from).abs()
+    }
+
+    fn ready(&self, drum: usize, now: Instant) -> bool {
+        self.current == drum || now.saturating_duration_since(self.ts) >= self.drums[drum].travel_time
+    }
+
+    // whether the arm is already at a drum playing the note
+    fn at(&self, key: u8) -> bool {
+        self.drums.get(self.current).is_some_and(|drum| drum.inputs.contains(&key))
+    }
+}
+
+// arms are free once they've had time to travel to the drum, and cost the distance they would travel
+impl Player for Arm {
+    fn plays(&self, note: u8) -> bool {
+        self.get(note).is_some()
+    }
+
+    fn free(&self, note: u8, at: Instant) -> bool {
+        self.get(note).is_some_and(|drum| self.ready(drum, at))
+    }
+
+    fn cost(&self, note: u8) -> f32 {
+        self.get(note).map(|drum| self.distance(drum)).unwrap_or_default()
+    }
+
+    fn last(&self) -> Option<Instant> {
+        Some(self.ts)
     }
 }
 
 pub struct DrumBot {
-    arms: Vec<RwLock<Arm>>,
+    arms: Mutex<Allocator<Arm>>,
     next: OptNode
 }
 
 impl DrumBot {
-    pub(crate) fn new(mappings: &Vec<ArmsConfig>) -> Self {
+    pub(crate) fn new(mappings: &[ArmsConfig]) -> Self {
         DrumBot {
-            arms: mappings.iter().map(|data| RwLock::new(Arm::new(data.0.clone()))).collect(),
+            arms: Mutex::new(Allocator::new(mappings.iter()
+                .enumerate()
+                .map(|(index, data)| Arm::new(index, data))
+                .collect())),
             next: RwLock::new(None)
         }
     }
 }
 
 impl Node for DrumBot {
-    fn call(&self, data: MidiData) -> () {
+    fn call(&self, data: MidiData) {
         // only allow note-ons (might be changed later)
-        if data.instruction != 0b1001 || data.velocity == 0 {
+        if data.instruction != NOTE_ON || data.velocity == 0 {
             return;
         }
 
@@ -67,43 +210,31 @@ impl Node for DrumBot {
             return;
         }
 
-        // simple check that an arm isn't already there
-        for (index, arm) in self.arms.iter().enumerate() {
-            let arm_lock = arm.read().unwrap();
-            if arm_lock.last_played == data.note {
-                info!(target: "DrumBot", "▩{} on arm {}", data.note, index);
-                self.next.call(MidiData {
+        // an arm already at the drum plays it, otherwise we prefer arms which have had time to travel there, then the
+        // closest arm, and finally whichever has been idle for the longest
+        let now = Instant::now();
+        let out_data = {
+            let mut arms = self.arms.lock().unwrap();
+            let chosen = arms.iter().position(|arm| arm.at(data.note))
+                .or_else(|| arms.choose(data.note, now).map(|candidate| candidate.index));
+            chosen.map(|index| {
+                let arm = &mut arms[index];
+                if !arm.at(data.note) {
+                    arm.ts = now;
+                    arm.current = arm.get(data.note).unwrap_or(arm.current);
+                }
+                let drum = &arm.drums[arm.current];
+                info!(target: "DrumBot", "▩{} on {}", drum.name, arm.name);
+                MidiData {
                     instruction: data.instruction,
                     channel: data.channel,
-                    note: arm_lock.get(data.note).unwrap(),
-                    velocity: data.velocity,
-                });
-                return;
-            }
-        }
-
-        // if no arms are at the drum, we want to use the least-used arm (whichever has been idle for the longest)
-        let mut arms: Vec<(usize, &RwLock<Arm>)> = self.arms.iter()
-            .enumerate()
-            .filter(|(_i, arm)| arm.read().unwrap().get(data.note).is_some())
-            .collect();
-        arms.sort_unstable_by_key(|(_i, arm)| arm.read().unwrap().ts);
-        if let Some((index, arm)) = arms.get(0) {
-            let note: u8;
-            {
-                let mut arm_lock = arm.write().unwrap();
-                arm_lock.ts = Instant::now();
-                arm_lock.last_played = data.note;
-                note = arm_lock.get(data.note).unwrap();
-            }
-            info!(target: "DrumBot", "▩{} on arm {}", data.note, index);
-            self.next.call(MidiData {
-                instruction: data.instruction,
-                channel: data.channel,
-                note,
-                velocity: data.velocity,
-            });
-
+                    note: drum.output,
+                    velocity: drum.velocity(data.velocity),
+                }
+            })
+        };
+        if let Some(out_data) = out_data {
+            self.next.call(out_data);
             return;
         }
 
@@ -115,11 +246,20 @@ impl Node for DrumBot {
         self.next.call(data);
     }
 
-    fn bind(&self, node: Weak<dyn Node>) -> () {
+    fn bind(&self, node: Weak<dyn Node>) {
         self.next.bind(node);
     }
 
     fn delay(&self) -> Duration {
         DRUMBOT_DELAY
     }
-}
\ No newline at end of file
+
+    fn state(&self) -> Value {
+        Value::Array(self.arms.lock().unwrap().iter().map(|arm| {
+            json!({
+                "name": arm.name,
+                "drum": arm.drums.get(arm.current).map(|drum| drum.name.as_str())
+            })
+        }).collect())
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use may::coroutine::sleep;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::config::StringMovement;
+use crate::data::{MidiData, NOTE_OFF, NOTE_ON};
+use crate::instruments::allocator::{Allocator, Candidate, Player};
+use crate::node::{dispatch, Node, OptNode};
+
+// above which fingerings are chosen greedily, note by note, rather than by searching every assignment
+const MAX_FINGERINGS: usize = 10_000;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrettedStringOptions {
+    pub(crate) strings: Vec<StringConfig>,
+    // the number of notes each string plays, including the open string
+    #[serde(default = "default_frets")]
+    pub(crate) frets: u8,
+    // the panning regression of every string without its own, defaulting to that measured on MechBass
+    pub(crate) shuttle: Option<StringMovement>,
+    // in seconds, within which notes are fingered together as a chord
+    #[serde(default)]
+    pub(crate) window: f32,
+    // the furthest apart, in frets, the fretted notes of a chord may be
+    pub(crate) max_span: Option<u8>
+}
+
+fn default_frets() -> u8 {
+    13
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StringConfig {
+    // the note of the open string
+    pub(crate) tuning: u8,
+    pub(crate) frets: Option<u8>,
+    // defaults to the string's index
+    pub(crate) channel: Option<u8>,
+    pub(crate) shuttle: Option<StringMovement>
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PlayedNote {
+    playing: bool,
+    note: u8,
+    delay: Duration,
+    ts: Instant
+}
+
+impl PlayedNote {
+    fn default(note: u8) -> Self {
+        PlayedNote {
+            playing: false,
+            note,
+            delay: Duration::default(),
+            ts: Instant::now()
+        }
+    }
+
+    fn play(note: u8, delay: Duration) -> Self {
+        PlayedNote {
+            playing: true,
+            note,
+            delay,
+            ts: Instant::now()
+        }
+    }
+}
+
+struct FretString {
+    tuning: u8,
+    frets: u8,
+    channel: u8,
+    // shared with the instrument, unless the string has a calibration of its own
+    shuttle: Arc<RwLock<StringMovement>>,
+    played: PlayedNote
+}
+
+impl FretString {
+    fn pan_time(&self, from: u8, to: u8) -> Duration {
+        self.shuttle.read().unwrap().time(from.saturating_sub(self.tuning), to.saturating_sub(self.tuning))
+    }
+
+    // the longest the shuttle may take to pan, between the open string and its highest fret
+    fn max_pan_time(&self) -> Duration {
+        self.shuttle.read().unwrap().time(0, self.frets)
+    }
+}
+
+// strings are free once their last note has been released before the note would be sent, and cost the seconds their
+// shuttle would take to pan
+impl Player for FretString {
+    fn plays(&self, note: u8) -> bool {
+        self.tuning <= note && self.tuning as u16 + self.frets as u16 > note as u16
+    }
+
+    fn free(&self, note: u8, at: Instant) -> bool {
+        let prev = self.played;
+        let sent = at.checked_sub(self.pan_time(prev.note, note)).unwrap_or(at);
+        !prev.playing && sent > prev.ts + prev.delay
+    }
+
+    fn cost(&self, note: u8) -> f32 {
+        self.pan_time(self.played.note, note).as_secs_f32()
+    }
+
+    fn last(&self) -> Option<Instant> {
+        Some(self.played.ts)
+    }
+}
+
+// the best assignment of a chord's notes onto strings found so far, by the number of notes assigned, then the number
+// of sounding notes cut short, and finally the total time spent panning
+struct Fingering {
+    strings: Vec<Option<usize>>,
+    score: (usize, usize, f32)
+}
+
+impl Fingering {
+    fn better(&self, score: (usize, usize, f32)) -> bool {
+        score.0 > self.score.0 || (score.0 == self.score.0 && (score.1, score.2) < (self.score.1, self.score.2))
+    }
+}
+
+// a shuttle fretting each string, which pans between notes in a time given by its calibration. Notes are delayed by
+// the longest a pan may take less their own, such that every note sounds in time
+pub(crate) struct FrettedString {
+    name: String,
+    strings: Mutex<Allocator<FretString>>,
+    calibration: Arc<RwLock<StringMovement>>,
+    // notes received within the window are fingered as a chord
+    window: Duration,
+    // the furthest apart, in frets, the fretted notes of a chord may be
+    max_span: Option<u8>,
+    pending: Mutex<Vec<MidiData>>,
+    // notes no string plays, by their channel and note, whose note-offs are passed through alongside them
+    passed: Mutex<HashSet<(u8, u8)>>,
+    next: OptNode
+}
+
+impl FrettedString {
+    pub(crate) fn new(
+        name: &str,
+        strings: &[StringConfig],
+        frets: u8,
+        calibration: StringMovement,
+        window: Duration,
+        max_span: Option<u8>
+    ) -> Self {
+        let calibration = Arc::new(RwLock::new(calibration));
+        FrettedString {
+            name: String::from(name),
+            strings: Mutex::new(Allocator::new(strings.iter().enumerate().map(|(index, string)| FretString {
+                tuning: string.tuning,
+                frets: string.frets.unwrap_or(frets),
+                channel: string.channel.unwrap_or(index as u8),
+                shuttle: match string.shuttle {
+                    Some(shuttle) => Arc::new(RwLock::new(shuttle)),
+                    None => calibration.clone()
+                },
+                played: PlayedNote::default(string.tuning)
+            }).collect())),
+            calibration,
+            window,
+            max_span,
+            pending: Mutex::new(Vec::new()),
+            passed: Mutex::new(HashSet::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    // the longest any string's shuttle may take to pan
+    fn max_pan_time(strings: &[FretString]) -> Duration {
+        strings.iter().map(FretString::max_pan_time).max().unwrap_or_default()
+    }
+
+    fn finger(&self, strings: &Allocator<FretString>, notes: &[u8], max_pan: Duration) -> Vec<Option<usize>> {
+        // every note sounds once the longest pan could have finished
+        let at = Instant::now() + max_pan;
+        let options: Vec<Vec<Candidate>> = notes.iter().map(|&note| strings.candidates(note, at)).collect();
+
+        let fingerings = options.iter().try_fold(1usize, |total, options| total.checked_mul(options.len() + 1));
+        if fingerings.is_none_or(|fingerings| fingerings > MAX_FINGERINGS) {
+            return self.finger_greedily(strings, notes, &options);
+        }
+        let mut best = Fingering { strings: vec![None; notes.len()], score: (0, 0, 0f32) };
+        let mut current = vec![None; notes.len()];
+        self.search(strings, notes, &options, 0, &mut current, (0, 0, 0f32), &mut best);
+        best.strings
+    }
+
+    // assigns each note in turn, those on the fewest strings first, to the best string left for it,
+    // preferring free strings and then the least panning
+    fn finger_greedily(&self, strings: &[FretString], notes: &[u8], options: &[Vec<Candidate>]) -> Vec<Option<usize>> {
+        let mut fingering = vec![None; notes.len()];
+        let mut order: Vec<usize> = (0..notes.len()).collect();
+        order.sort_by_key(|&index| options[index].len());
+        for index in order {
+            let mut options: Vec<&Candidate> = options[index].iter().collect();
+            options.sort_by(|a, b| b.free.cmp(&a.free).then(a.cost.total_cmp(&b.cost)));
+            for candidate in options {
+                if fingering.contains(&Some(candidate.index)) {
+                    continue;
+                }
+                fingering[index] = Some(candidate.index);
+                if self.playable(strings, notes, &fingering) {
+                    break;
+                }
+                fingering[index] = None;
+            }
+        }
+        fingering
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        strings: &[FretString],
+        notes: &[u8],
+        options: &[Vec<Candidate>],
+        index: usize,
+        current: &mut Vec<Option<usize>>,
+        score: (usize, usize, f32),
+        best: &mut Fingering
+    ) {
+        if index == notes.len() {
+            if best.better(score) {
+                best.strings = current.clone();
+                best.score = score;
+            }
+            return;
+        }
+        for candidate in options[index].iter() {
+            if current.contains(&Some(candidate.index)) {
+                continue;
+            }
+            current[index] = Some(candidate.index);
+            if self.playable(strings, notes, current) {
+                // strings which aren't free would cut their sounding note short
+                let score = (score.0 + 1, score.1 + !candidate.free as usize, score.2 + candidate.cost);
+                self.search(strings, notes, options, index + 1, current, score, best);
+            }
+            current[index] = None;
+        }
+        // notes may go unplayed, should there be no fingering including them
+        self.search(strings, notes, options, index + 1, current, score, best);
+    }
+
+    // whether the fretted (i.e. not open) notes of the fingering lie within the maximum span
+    fn playable(&self, strings: &[FretString], notes: &[u8], fingering: &[Option<usize>]) -> bool {
+        let Some(max_span) = self.max_span else {
+            return true;
+        };
+        let frets = notes.iter().zip(fingering)
+            .filter_map(|(note, string)| string.map(|string| note - strings[string].tuning))
+            .filter(|fret| *fret > 0);
+        let (min, max) = frets.fold((u8::MAX, 0), |(min, max), fret| (min.min(fret), max.max(fret)));
+        min > max || max - min <= max_span
+    }
+
+    // fingers the note-ons of a chord together, returning each message with its delay
+    fn strike(&self, strings: &mut Allocator<FretString>, chord: &[MidiData], max_pan: Duration) -> Vec<(MidiData, f32)> {
+        let mut messages = Vec::new();
+        // chords are limited to as many notes as there are strings, as any more couldn't be played at once
+        for chord in chord.chunks(strings.len().max(1)) {
+            let notes: Vec<u8> = chord.iter().map(|data| data.note).collect();
+            let fingering = self.finger(strings, &notes, max_pan);
+            for (data, string) in chord.iter().zip(fingering) {
+                let Some(string) = string else {
+                    if strings.iter().any(|string| string.plays(data.note)) {
+                        warn!(target: &self.name, "Unable to finger {} alongside the rest of its chord", data.note);
+                    } else {
+                        // transparently send all unrecognised notes to channel 0
+                        self.passed.lock().unwrap().insert((data.channel, data.note));
+                        messages.push((MidiData { channel: 0, ..*data }, 0f32));
+                    }
+                    continue;
+                };
+                let string = &mut strings[string];
+                let prev = string.played;
+                if prev.playing {
+                    warn!(target: &self.name, "Note {} overriden by {} - channel {}", prev.note, data.note, string.channel);
+                }
+                let delay = max_pan.saturating_sub(string.pan_time(prev.note, data.note));
+                string.played = PlayedNote::play(data.note, delay);
+                info!(target: &self.name, "⬇{} on channel {}", data.note, string.channel);
+                messages.push((MidiData { channel: string.channel, ..*data }, delay.as_secs_f32()));
+            }
+        }
+        messages
+    }
+
+    fn release(&self, strings: &mut Allocator<FretString>, data: MidiData) -> Option<(MidiData, f32)> {
+        let Some(string) = strings.iter_mut().find(|string| string.played.playing && string.played.note == data.note) else {
+            if self.passed.lock().unwrap().remove(&(data.channel, data.note)) {
+                return Some((MidiData { channel: 0, ..data }, 0f32));
+            }
+            warn!(target: &self.name, "Released note {}, but none were playing", data.note);
+            return None;
+        };
+        let prev = &mut string.played;
+        prev.playing = false;
+        prev.ts = Instant::now();
+        info!(target: &self.name, "⬆{} on channel {}", data.note, string.channel);
+        Some((MidiData { channel: string.channel, ..data }, prev.delay.as_secs_f32()))
+    }
+
+    // fingers each run of note-ons together as a chord, keeping the order of the batch such that a note released and
+    // struck again within the window is played rather than cut short. Returns each message with its delay
+    fn play(&self, batch: Vec<MidiData>) -> Vec<(MidiData, f32)> {
+        let mut strings = self.strings.lock().unwrap();
+        let max_pan = FrettedString::max_pan_time(&strings);
+        let mut messages = Vec::new();
+        let mut chord = Vec::new();
+        for data in batch {
+            if data.is_note_on() {
+                chord.push(data);
+            } else if data.is_note_off() {
+                messages.extend(self.strike(&mut strings, &std::mem::take(&mut chord), max_pan));
+                messages.extend(self.release(&mut strings, data));
+            }
+        }
+        messages.extend(self.strike(&mut strings, &chord, max_pan));
+        messages
+    }
+}
+
+impl Node for FrettedString {
+    fn call(&self, data: MidiData) {
+        let (NOTE_OFF | NOTE_ON) = data.instruction else {
+            return;
+        };
+
+        let batch = if self.window.is_zero() {
+            vec![data]
+        } else {
+            // the first note of a window collects every note received until it closes
+            {
+                let mut pending = self.pending.lock().unwrap();
+                pending.push(data);
+                if pending.len() > 1 {
+                    return;
+                }
+            }
+            sleep(self.window);
+            std::mem::take(&mut *self.pending.lock().unwrap())
+        };
+
+        let start = Instant::now();
+        let messages = self.play(batch);
+        dispatch(&self.name, &self.next, start, Duration::ZERO, messages);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.window + FrettedString::max_pan_time(&self.strings.lock().unwrap())
+    }
+
+    fn state(&self) -> Value {
+        let calibration = *self.calibration.read().unwrap();
+        json!({
+            "linear": calibration.linear,
+            "exponential": calibration.exponential,
+            "quadratic": calibration.quadratic,
+            "strings": self.strings.lock().unwrap().iter().map(|string| {
+                json!({ "note": string.played.note, "playing": string.played.playing })
+            }).collect::<Vec<_>>()
+        })
+    }
+
+    fn set(&self, param: &str, value: &Value) -> Result<(), String> {
+        let value = value.as_f64()
+            .map(|value| value as f32)
+            .filter(|value| value.is_finite())
+            .ok_or(format!("Expected a number, got {}", value))?;
+        let mut calibration = self.calibration.write().unwrap();
+        match param {
+            "linear" => calibration.linear = value,
+            "exponential" => calibration.exponential = value,
+            "quadratic" => calibration.quadratic = value,
+            param => return Err(format!("Unknown parameter: {}", param))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instruments::MechBass;
+    use super::*;
+
+    fn guitar(tunings: &[u8], max_span: Option<u8>) -> FrettedString {
+        let strings: Vec<StringConfig> = tunings.iter()
+            .map(|&tuning| StringConfig { tuning, frets: None, channel: None, shuttle: None })
+            .collect();
+        FrettedString::new("Guitar", &strings, 12, MechBass::CALIBRATION, Duration::ZERO, max_span)
+    }
+
+    fn on(note: u8) -> MidiData {
+        MidiData { instruction: NOTE_ON, channel: 0, note, velocity: 100 }
+    }
+
+    fn off(note: u8) -> MidiData {
+        MidiData { instruction: NOTE_OFF, channel: 0, note, velocity: 0 }
+    }
+
+    #[test]
+    fn chords_are_fingered_across_distinct_strings() {
+        let guitar = guitar(&[40, 45, 50], None);
+        let messages = guitar.play(vec![on(50), on(52), on(55)]);
+        let mut channels: Vec<u8> = messages.iter().map(|(data, _delay)| data.channel).collect();
+        channels.sort_unstable();
+        assert_eq!(channels, [0, 1, 2]);
+    }
+
+    #[test]
+    fn fretted_notes_are_kept_within_the_span() {
+        let guitar = guitar(&[40, 45], Some(2));
+        let strings = guitar.strings.lock().unwrap();
+        let max_pan = FrettedString::max_pan_time(&strings);
+        // frets 3 and 7 are too far apart, leaving a note unplayed
+        let fingering = guitar.finger(&strings, &[43, 52], max_pan);
+        assert_eq!(fingering.iter().filter(|string| string.is_some()).count(), 1);
+        // open strings don't count towards the span
+        assert_eq!(guitar.finger(&strings, &[40, 52], max_pan), [Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn busy_strings_are_avoided() {
+        let guitar = guitar(&[40, 45], None);
+        guitar.play(vec![on(47)]);
+        let messages = guitar.play(vec![on(48)]);
+        let strings = guitar.strings.lock().unwrap();
+        assert!(strings.iter().all(|string| string.played.playing));
+        assert_ne!(messages[0].0.channel, strings.iter().position(|string| string.played.note == 47).unwrap() as u8);
+    }
+
+    #[test]
+    fn notes_struck_again_within_the_window_keep_sounding() {
+        let guitar = guitar(&[40, 45], None);
+        guitar.play(vec![on(40)]);
+        let messages = guitar.play(vec![off(40), on(40)]);
+        let sent: Vec<(u8, u8)> = messages.iter().map(|(data, _delay)| (data.instruction, data.note)).collect();
+        assert_eq!(sent, [(NOTE_OFF, 40), (NOTE_ON, 40)]);
+        assert!(guitar.strings.lock().unwrap().iter().any(|string| string.played.playing && string.played.note == 40));
+    }
+
+    #[test]
+    fn unplayable_notes_pass_through_with_their_note_off() {
+        let guitar = guitar(&[40], None);
+        let on = MidiData { channel: 3, ..on(20) };
+        let off = MidiData { channel: 3, ..off(20) };
+        assert_eq!(guitar.play(vec![on]), [(MidiData { channel: 0, ..on }, 0f32)]);
+        assert_eq!(guitar.play(vec![off]), [(MidiData { channel: 0, ..off }, 0f32)]);
+        assert!(guitar.play(vec![off]).is_empty());
+    }
+
+    #[test]
+    fn large_chords_are_fingered_greedily() {
+        // 13 strings tuned a semitone apart each give every note of the chord many candidates
+        let tunings: Vec<u8> = (40..53).collect();
+        let guitar = guitar(&tunings, None);
+        let notes: Vec<u8> = (52..64).collect();
+        let strings = guitar.strings.lock().unwrap();
+        let fingering = guitar.finger(&strings, &notes, FrettedString::max_pan_time(&strings));
+        let mut assigned: Vec<usize> = fingering.iter().flatten().copied().collect();
+        assigned.sort_unstable();
+        assigned.dedup();
+        assert_eq!(assigned.len(), notes.len());
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use may::coroutine::sleep;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::config::VelocityCurve;
+use crate::data::MidiData;
+use crate::instruments::allocator::{Allocator, Player};
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstrumentOptions {
+    pub(crate) actuators: Vec<ActuatorConfig>,
+    // whether note-offs are sent to the actuator which played the note, rather than dropped
+    #[serde(default)]
+    pub(crate) note_offs: bool
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ActuatorConfig {
+    pub(crate) name: Option<String>,
+    // the notes the actuator plays, either as an inclusive range, a list, or both
+    pub(crate) range: Option<(u8, u8)>,
+    #[serde(default)]
+    pub(crate) notes: Vec<u8>,
+    // the note and channel sent, defaulting to those received
+    pub(crate) output: Option<u8>,
+    pub(crate) channel: Option<u8>,
+    // seconds between the actuator receiving a note and sounding it
+    #[serde(default)]
+    pub(crate) latency: f32,
+    // the fewest seconds between consecutive notes
+    #[serde(default)]
+    pub(crate) retrigger: f32,
+    pub(crate) velocity: Option<VelocityCurve>
+}
+
+struct Actuator {
+    name: String,
+    notes: Vec<u8>,
+    output: Option<u8>,
+    channel: Option<u8>,
+    latency: Duration,
+    retrigger: Duration,
+    velocity: Option<VelocityCurve>,
+    last: Option<Instant>
+}
+
+impl Actuator {
+    fn from_config(index: usize, config: &ActuatorConfig) -> Self {
+        let mut notes = config.notes.clone();
+        if let Some((low, high)) = config.range {
+            notes.extend(low..=high);
+        }
+        Actuator {
+            name: config.name.clone().unwrap_or_else(|| format!("actuator {}", index)),
+            notes,
+            output: config.output,
+            channel: config.channel,
+            latency: Duration::from_secs_f32(config.latency),
+            retrigger: Duration::from_secs_f32(config.retrigger),
+            velocity: config.velocity.clone(),
+            last: None
+        }
+    }
+
+    fn ready(&self, now: Instant) -> bool {
+        self.last.is_none_or(|last| now.duration_since(last) >= self.retrigger)
+    }
+
+    fn map(&self, data: MidiData) -> MidiData {
+        MidiData {
+            instruction: data.instruction,
+            channel: self.channel.unwrap_or(data.channel),
+            note: self.output.unwrap_or(data.note),
+            velocity: match (&self.velocity, data.is_note_on()) {
+                (Some(curve), true) => curve.apply(data.velocity),
+                _ => data.velocity
+            }
+        }
+    }
+}
+
+// actuators are free once they may retrigger, and never move
+impl Player for Actuator {
+    fn plays(&self, note: u8) -> bool {
+        self.notes.contains(&note)
+    }
+
+    fn free(&self, _note: u8, at: Instant) -> bool {
+        self.ready(at)
+    }
+
+    fn cost(&self, _note: u8) -> f32 {
+        0f32
+    }
+
+    fn last(&self) -> Option<Instant> {
+        self.last
+    }
+}
+
+// an instrument described entirely by its config, as a set of actuators each playing some notes. Notes are delayed
+// by the slowest actuator's latency less their own, such that every actuator sounds in time
+pub(crate) struct Instrument {
+    name: String,
+    actuators: Mutex<Allocator<Actuator>>,
+    note_offs: bool,
+    max_latency: Duration,
+    // the actuator each sounding note was played on
+    sounding: Mutex<HashMap<(u8, u8), usize>>,
+    // notes no actuator plays, whose note-offs are passed through alongside them
+    passed: Mutex<HashSet<(u8, u8)>>,
+    next: OptNode
+}
+
+impl Instrument {
+    pub(crate) fn new(name: &str, actuators: &[ActuatorConfig], note_offs: bool) -> Self {
+        let actuators: Vec<Actuator> = actuators.iter()
+            .enumerate()
+            .map(|(index, config)| Actuator::from_config(index, config))
+            .collect();
+        Instrument {
+            name: String::from(name),
+            max_latency: actuators.iter().map(|actuator| actuator.latency).max().unwrap_or_default(),
+            actuators: Mutex::new(Allocator::new(actuators)),
+            note_offs,
+            sounding: Mutex::new(HashMap::new()),
+            passed: Mutex::new(HashSet::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    // of the actuators able to play the note, prefers whichever has been idle for the longest
+    fn note_on(&self, data: MidiData) -> Option<(MidiData, Duration)> {
+        let now = Instant::now();
+        let mut actuators = self.actuators.lock().unwrap();
+        let Some(chosen) = actuators.choose(data.note, now) else {
+            warn!(target: &self.name, "No actuators allocated to {}, performing direct pass-through!", data.note);
+            self.passed.lock().unwrap().insert((data.channel, data.note));
+            return Some((data, Duration::ZERO));
+        };
+        if !chosen.free {
+            warn!(target: &self.name, "Dropping {}, as every actuator able to play it is retriggering", data.note);
+            return None;
+        }
+
+        let index = chosen.index;
+        let actuator = &mut actuators[index];
+        actuator.last = Some(now);
+        info!(target: &self.name, "⬇{} on {}", data.note, actuator.name);
+        if self.note_offs {
+            self.sounding.lock().unwrap().insert((data.channel, data.note), index);
+        }
+        Some((actuator.map(data), self.max_latency.saturating_sub(actuator.latency)))
+    }
+
+    fn note_off(&self, data: MidiData) -> Option<(MidiData, Duration)> {
+        if self.passed.lock().unwrap().remove(&(data.channel, data.note)) {
+            return Some((data, Duration::ZERO));
+        }
+        if !self.note_offs {
+            return None;
+        }
+        let Some(index) = self.sounding.lock().unwrap().remove(&(data.channel, data.note)) else {
+            debug!(target: &self.name, "Released note {}, but none were playing", data.note);
+            return None;
+        };
+        let actuators = self.actuators.lock().unwrap();
+        let actuator = &actuators[index];
+        info!(target: &self.name, "⬆{} on {}", data.note, actuator.name);
+        Some((actuator.map(data), self.max_latency.saturating_sub(actuator.latency)))
+    }
+}
+
+impl Node for Instrument {
+    fn call(&self, data: MidiData) {
+        let out = if data.is_note_on() {
+            self.note_on(data)
+        } else if data.is_note_off() {
+            self.note_off(data)
+        } else {
+            Some((data, Duration::ZERO))
+        };
+        if let Some((out, delay)) = out {
+            sleep(delay);
+            self.next.call(out);
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.max_latency
+    }
+
+    fn state(&self) -> Value {
+        let now = Instant::now();
+        Value::Array(self.actuators.lock().unwrap().iter().map(|actuator| json!({
+            "name": actuator.name,
+            "ready": actuator.ready(now)
+        })).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{NOTE_OFF, NOTE_ON};
+    use super::*;
+
+    fn actuator(notes: Vec<u8>, output: Option<u8>, latency: f32, retrigger: f32) -> ActuatorConfig {
+        ActuatorConfig { name: None, range: None, notes, output, channel: Some(9), latency, retrigger, velocity: None }
+    }
+
+    fn message(instruction: u8, note: u8) -> MidiData {
+        MidiData { instruction, channel: 0, note, velocity: if instruction == NOTE_ON { 100 } else { 0 } }
+    }
+
+    #[test]
+    fn notes_are_delayed_to_the_slowest_latency() {
+        let instrument = Instrument::new("Instrument", &[
+            actuator(vec![36], Some(60), 0.05, 0.0),
+            actuator(vec![38], None, 0.0, 0.0)
+        ], true);
+        assert_eq!(instrument.delay(), Duration::from_secs_f32(0.05));
+        let played = MidiData { channel: 9, note: 60, ..message(NOTE_ON, 36) };
+        assert_eq!(instrument.note_on(message(NOTE_ON, 36)), Some((played, Duration::ZERO)));
+        let played = MidiData { channel: 9, ..message(NOTE_ON, 38) };
+        assert_eq!(instrument.note_on(message(NOTE_ON, 38)), Some((played, Duration::from_secs_f32(0.05))));
+        // note-offs follow their note-on onto its actuator
+        let released = MidiData { channel: 9, note: 60, ..message(NOTE_OFF, 36) };
+        assert_eq!(instrument.note_off(message(NOTE_OFF, 36)), Some((released, Duration::ZERO)));
+    }
+
+    #[test]
+    fn retriggering_actuators_are_skipped() {
+        let instrument = Instrument::new("Instrument", &[
+            actuator(vec![36], Some(36), 0.0, 10.0),
+            actuator(vec![36], Some(37), 0.0, 10.0)
+        ], false);
+        let notes: Vec<Option<u8>> = (0..3)
+            .map(|_| instrument.note_on(message(NOTE_ON, 36)).map(|(data, _delay)| data.note))
+            .collect();
+        assert_eq!(notes, [Some(36), Some(37), None]);
+        assert_eq!(instrument.note_off(message(NOTE_OFF, 36)), None);
+    }
+
+    #[test]
+    fn notes_no_actuator_plays_pass_through_with_their_note_off() {
+        let instrument = Instrument::new("Instrument", &[actuator(vec![36], None, 0.05, 0.0)], false);
+        assert_eq!(instrument.note_on(message(NOTE_ON, 40)), Some((message(NOTE_ON, 40), Duration::ZERO)));
+        assert_eq!(instrument.note_off(message(NOTE_OFF, 40)), Some((message(NOTE_OFF, 40), Duration::ZERO)));
+        assert_eq!(instrument.note_off(message(NOTE_OFF, 40)), None);
+    }
+}
@@ -1,7 +1,24 @@
 mod mechbass;
 mod drumbot;
+mod voice_allocator;
+mod instrument;
+mod fretted_string;
+mod allocator;
+#[cfg(feature = "python")]
 mod python;
+#[cfg(feature = "rhai")]
+mod rhai_node;
+#[cfg(feature = "wasm")]
+mod wasm_node;
 
 pub(crate) use mechbass::MechBass;
-pub(crate) use drumbot::DrumBot;
-pub(crate) use python::PyNode;
\ No newline at end of file
+pub(crate) use drumbot::{ArmsConfig, DrumBot, DrumBotOptions};
+pub(crate) use voice_allocator::{VoiceAllocator, VoiceAllocatorOptions};
+pub(crate) use instrument::{Instrument, InstrumentOptions};
+pub(crate) use fretted_string::{FrettedString, FrettedStringOptions};
+#[cfg(feature = "python")]
+pub(crate) use python::{Execution, PyNode, PyNodeOptions};
+#[cfg(feature = "rhai")]
+pub(crate) use rhai_node::{RhaiNode, RhaiNodeOptions};
+#[cfg(feature = "wasm")]
+pub(crate) use wasm_node::{WasmNode, WasmNodeOptions};
\ No newline at end of file
@@ -0,0 +1,298 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use log::{error, info, warn};
+use may::sync::RwLock;
+use may::sync::mpsc::channel;
+use pyo3::{intern, Bound, Py, PyAny, PyErr, PyObject, PyResult, Python, ToPyObject};
+use pyo3::types::{PyAnyMethods, PyDict, PyDictMethods, PyList, PyListMethods, PyInt, PyModule, PyTuple};
+use serde::Deserialize;
+use serde_yml::Value;
+use crate::data::MidiData;
+use crate::node::{dispatch, Node, OptNode};
+use module::{Message, NodeContext, prepare, with_context};
+use worker::Pool;
+
+mod module;
+mod reload;
+mod worker;
+
+// each node requires a distinct module name, as python will otherwise re-use the namespace of a previous node
+static MODULE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PyNodeOptions {
+    pub(crate) source: String,
+    pub(crate) duration: f32,
+    pub(crate) class: Option<String>,
+    pub(crate) params: Option<serde_yml::Value>,
+    // defaults to the node's duration
+    pub(crate) timeout: Option<f32>,
+    #[serde(default)]
+    pub(crate) on_timeout: TimeoutPolicy,
+    #[serde(default = "default_workers")]
+    pub(crate) workers: usize
+}
+
+// what a PyNode emits in place of a call which has overrun its timeout
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TimeoutPolicy {
+    #[default]
+    Drop,
+    Pass,
+    Fallback(Vec<(u8, u8, u8, u8, f32)>)
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+pub(crate) struct PyNode {
+    name: String,
+    path: PathBuf,
+    class: Option<String>,
+    params: Option<Value>,
+    duration: Duration,
+    execution: Execution,
+    // either the module itself, or an instance of the configured class, both of which provide `call`
+    target: Arc<RwLock<Py<PyAny>>>,
+    pool: Pool,
+    next: OptNode,
+}
+
+pub(crate) struct Execution {
+    pub(crate) timeout: Duration,
+    pub(crate) on_timeout: TimeoutPolicy,
+    pub(crate) workers: usize
+}
+
+impl PyNode {
+    pub(crate) fn new(
+        name: &str,
+        path: &Path,
+        duration: Duration,
+        class: Option<&str>,
+        params: Option<&Value>,
+        execution: Execution
+    ) -> Result<Self, PyErr> {
+        prepare();
+        let context = NodeContext { name: String::from(name), next: None };
+        let target = with_context(context, || load(path, class, params))?;
+
+        Ok(PyNode {
+            name: String::from(name),
+            path: path.to_path_buf(),
+            class: class.map(String::from),
+            params: params.cloned(),
+            duration,
+            pool: Pool::new(name, execution.workers),
+            execution,
+            target: Arc::new(RwLock::new(target)),
+            next: RwLock::new(None)
+        })
+    }
+
+    fn context(&self) -> NodeContext {
+        NodeContext {
+            name: self.name.clone(),
+            next: self.next.read().unwrap().clone()
+        }
+    }
+
+    // the new module is only swapped in once it has compiled and been initialised, otherwise the old one is kept
+    fn reload(&self) {
+        let loaded = with_context(self.context(), || {
+            load(&self.path, self.class.as_deref(), self.params.as_ref())
+        });
+        match loaded {
+            Ok(target) => {
+                let old = std::mem::replace(&mut *self.target.write().unwrap(), target);
+                with_context(self.context(), || Python::with_gil(|py| shutdown(old.bind(py))));
+                info!(target: "PyNode", "Reloaded {}", self.path.display());
+            }
+            Err(error) => {
+                error!(target: "PyNode", "Failed to reload {}, keeping previous version: {}", self.path.display(), error);
+            }
+        }
+    }
+}
+
+fn load(path: &Path, class: Option<&str>, params: Option<&Value>) -> Result<Py<PyAny>, PyErr> {
+    let source = read_to_string(path)?;
+    Python::with_gil(|py| {
+        let module_name = format!("pynode_{}", MODULE_COUNT.fetch_add(1, Ordering::Relaxed));
+        let file_name = path.to_string_lossy();
+        let module_bound = PyModule::from_code_bound(py, &source, &file_name, &module_name)?;
+        let params = match params {
+            Some(params) => to_py(py, params)?,
+            None => PyDict::new_bound(py).into_any().unbind()
+        };
+
+        // class-based nodes receive their params on construction, and keep their state on the instance
+        let target = if let Some(class) = class {
+            module_bound.getattr(class)?.call1((params,))?
+        } else {
+            if module_bound.hasattr(intern!(py, "init"))? {
+                module_bound.getattr(intern!(py, "init"))?.call1((params,))?;
+            }
+            module_bound.into_any()
+        };
+
+        target
+            .getattr(intern!(py, "call"))?
+            .getattr(intern!(py, "__call__"))?;
+
+        Ok(target.unbind())
+    })
+}
+
+fn shutdown(target: &Bound<'_, PyAny>) {
+    let py = target.py();
+    if !target.hasattr(intern!(py, "shutdown")).unwrap_or(false) {
+        return;
+    }
+    if let Err(error) = target.call_method0(intern!(py, "shutdown")) {
+        error!(target: "PyNode", "Python shutdown failed due to error: {}", error);
+    }
+}
+
+// convert the free-form params mapping from the node config into native python objects
+fn to_py(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.to_object(py),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                i.to_object(py)
+            } else if let Some(u) = n.as_u64() {
+                u.to_object(py)
+            } else {
+                n.as_f64().unwrap_or_default().to_object(py)
+            }
+        }
+        Value::String(s) => s.to_object(py),
+        Value::Sequence(seq) => {
+            let list = PyList::empty_bound(py);
+            for item in seq {
+                list.append(to_py(py, item)?)?;
+            }
+            list.into_any().unbind()
+        }
+        Value::Mapping(map) => {
+            let dict = PyDict::new_bound(py);
+            for (key, item) in map {
+                dict.set_item(to_py(py, key)?, to_py(py, item)?)?;
+            }
+            dict.into_any().unbind()
+        }
+        Value::Tagged(tagged) => to_py(py, &tagged.value)?
+    })
+}
+
+impl Node for PyNode {
+    fn call(&self, data: MidiData) {
+        info!(target: "PyNode", "Recieved {:?}", data);
+        let ts_start = Instant::now();
+        let (sender, receiver) = channel();
+        let target = self.target.clone();
+        let context = self.context();
+        let args = (data.instruction, data.channel, data.note, data.velocity);
+        let task = self.pool.submit(move |py| {
+            let output = with_context(context, || {
+                let target = target.read().unwrap().clone_ref(py);
+                let target = target.bind(py);
+
+                // Get the function and call it.
+                target.getattr(intern!(py, "call"))?
+                    .call1(args)
+                    .and_then(|out| extract_messages(&out))
+            });
+            let _ = sender.send(output);
+        });
+
+        let messages = match receiver.recv_timeout(self.execution.timeout) {
+            Ok(Ok(messages)) => messages,
+            Ok(Err(error)) => {
+                error!(target: "PyNode", "Python failed due to error: {}", error);
+                return;
+            }
+            Err(_) => {
+                self.pool.interrupt(&task);
+                warn!(
+                    target: "PyNode",
+                    "Exceeded timeout of {:?}, applying {:?}",
+                    self.execution.timeout,
+                    self.execution.on_timeout
+                );
+                match &self.execution.on_timeout {
+                    TimeoutPolicy::Drop => return,
+                    TimeoutPolicy::Pass => vec![(data, 0f32)],
+                    TimeoutPolicy::Fallback(fallback) => fallback.iter()
+                        .map(|&(instruction, channel, note, velocity, delay)| {
+                            (MidiData { instruction, channel, note, velocity }, delay)
+                        })
+                        .collect()
+                }
+            }
+        };
+
+        dispatch("PyNode", &self.next, ts_start, self.duration, messages);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.duration
+    }
+}
+
+// scripts may return None, a single message, or a list of messages, where messages are either
+// (instruction, channel, note, velocity, delay) tuples or objects with matching attributes
+fn extract_messages(out: &Bound<'_, PyAny>) -> PyResult<Vec<(MidiData, f32)>> {
+    if out.is_none() {
+        return Ok(Vec::new());
+    }
+    // tuples may either hold the fields of a single message, or several messages
+    let is_single = out.is_instance_of::<PyTuple>()
+        && out.get_item(0).map(|item| item.is_instance_of::<PyInt>()).unwrap_or(false);
+    if !is_single && (out.is_instance_of::<PyList>() || out.is_instance_of::<PyTuple>()) {
+        return out.iter()?.map(|item| extract_message(&item?)).collect();
+    }
+    Ok(vec![extract_message(out)?])
+}
+
+fn extract_message(out: &Bound<'_, PyAny>) -> PyResult<(MidiData, f32)> {
+    if let Ok(message) = out.downcast::<Message>() {
+        return Ok(message.borrow().to_data());
+    }
+    if out.is_instance_of::<PyTuple>() {
+        let (instruction, channel, note, velocity, delay) = out.extract::<(u8, u8, u8, u8, f32)>()?;
+        return Ok((MidiData { instruction, channel, note, velocity }, delay));
+    }
+    let py = out.py();
+    let delay = if out.hasattr(intern!(py, "delay"))? {
+        out.getattr(intern!(py, "delay"))?.extract::<f32>()?
+    } else {
+        0f32
+    };
+    Ok((MidiData {
+        instruction: out.getattr(intern!(py, "instruction"))?.extract()?,
+        channel: out.getattr(intern!(py, "channel"))?.extract()?,
+        note: out.getattr(intern!(py, "note"))?.extract()?,
+        velocity: out.getattr(intern!(py, "velocity"))?.extract()?,
+    }, delay))
+}
+
+impl Drop for PyNode {
+    fn drop(&mut self) {
+        with_context(self.context(), || Python::with_gil(|py| {
+            shutdown(self.target.read().unwrap().bind(py));
+        }));
+    }
+}
@@ -0,0 +1,207 @@
+// pyo3's generated wrappers trip this lint on functions returning PyResult
+#![allow(clippy::useless_conversion)]
+
+use std::cell::RefCell;
+use std::sync::{Once, Weak};
+use std::time::{Duration, Instant};
+use log::{log, Level};
+use may::coroutine::sleep;
+use may::go;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use crate::config::graph::EPOCH;
+use crate::data::{MidiData, CONTROL_CHANGE, NOTE_OFF, NOTE_ON};
+use crate::node::Node;
+use crate::transforms::clock;
+
+// the node currently executing python, which scripts implicitly refer to when logging or scheduling
+pub(super) struct NodeContext {
+    pub(super) name: String,
+    pub(super) next: Option<Weak<dyn Node>>
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<NodeContext>> = const { RefCell::new(None) };
+}
+
+pub(super) fn with_context<R>(context: NodeContext, f: impl FnOnce() -> R) -> R {
+    let prev = CONTEXT.replace(Some(context));
+    let result = f();
+    CONTEXT.set(prev);
+    result
+}
+
+static PREPARE: Once = Once::new();
+
+// the mechsync module must be registered before the interpreter is initialised
+pub(super) fn prepare() {
+    PREPARE.call_once(|| {
+        pyo3::append_to_inittab!(mechsync);
+        pyo3::prepare_freethreaded_python();
+    });
+}
+
+#[pyclass(module = "mechsync")]
+#[derive(Clone)]
+pub(super) struct Message {
+    #[pyo3(get, set)]
+    instruction: u8,
+    #[pyo3(get, set)]
+    channel: u8,
+    #[pyo3(get, set)]
+    note: u8,
+    #[pyo3(get, set)]
+    velocity: u8,
+    #[pyo3(get, set)]
+    delay: f32
+}
+
+#[pymethods]
+impl Message {
+    #[new]
+    #[pyo3(signature = (instruction, channel, note, velocity, delay = 0f32))]
+    fn new(instruction: u8, channel: u8, note: u8, velocity: u8, delay: f32) -> Self {
+        Message { instruction, channel, note, velocity, delay }
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (channel, note, velocity = 127, delay = 0f32))]
+    fn note_on(channel: u8, note: u8, velocity: u8, delay: f32) -> Self {
+        Message::new(NOTE_ON, channel, note, velocity, delay)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (channel, note, velocity = 0, delay = 0f32))]
+    fn note_off(channel: u8, note: u8, velocity: u8, delay: f32) -> Self {
+        Message::new(NOTE_OFF, channel, note, velocity, delay)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (channel, controller, value, delay = 0f32))]
+    fn control_change(channel: u8, controller: u8, value: u8, delay: f32) -> Self {
+        Message::new(CONTROL_CHANGE, channel, controller, value, delay)
+    }
+
+    // note-ons with zero velocity are treated as note-offs, as per the MIDI spec
+    #[getter]
+    fn is_note_on(&self) -> bool {
+        self.instruction == NOTE_ON && self.velocity != 0
+    }
+
+    #[getter]
+    fn is_note_off(&self) -> bool {
+        self.instruction == NOTE_OFF || (self.instruction == NOTE_ON && self.velocity == 0)
+    }
+
+    #[getter]
+    fn is_control_change(&self) -> bool {
+        self.instruction == CONTROL_CHANGE
+    }
+
+    #[getter]
+    fn controller(&self) -> Option<u8> {
+        self.is_control_change().then_some(self.note)
+    }
+
+    #[getter]
+    fn value(&self) -> Option<u8> {
+        self.is_control_change().then_some(self.velocity)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Message(instruction={}, channel={}, note={}, velocity={}, delay={})",
+            self.instruction, self.channel, self.note, self.velocity, self.delay
+        )
+    }
+}
+
+impl Message {
+    pub(super) fn to_data(&self) -> (MidiData, f32) {
+        (MidiData {
+            instruction: self.instruction,
+            channel: self.channel,
+            note: self.note,
+            velocity: self.velocity,
+        }, self.delay)
+    }
+}
+
+// seconds elapsed since the graph was started
+#[pyfunction]
+fn time() -> f64 {
+    EPOCH.elapsed().as_secs_f64()
+}
+
+// the tempo followed by the most recently ticking Clock node, in beats per minute, if any
+#[pyfunction]
+fn tempo() -> Option<f64> {
+    clock::tempo()
+}
+
+// send a message to the node's successor at an absolute graph time, ignoring the message's own delay
+#[pyfunction]
+fn schedule(message: &Message, at: f64) -> PyResult<()> {
+    let next = CONTEXT.with_borrow(|context| context.as_ref().and_then(|context| context.next.clone()))
+        .ok_or(PyRuntimeError::new_err("schedule can only be called once the node is bound"))?;
+    let (data, _delay) = message.to_data();
+    let target = Duration::try_from_secs_f64(at.max(0f64)).ok()
+        .and_then(|at| EPOCH.checked_add(at))
+        .ok_or(PyValueError::new_err(format!("Can't schedule a message at {}", at)))?;
+    go!(move || {
+        let now = Instant::now();
+        if target > now {
+            sleep(target - now);
+        }
+        if let Some(node) = next.upgrade() {
+            node.call(data);
+        }
+    });
+    Ok(())
+}
+
+fn log_message(level: Level, message: &str) {
+    CONTEXT.with_borrow(|context| {
+        let target = context.as_ref().map(|context| context.name.as_str()).unwrap_or("PyNode");
+        log!(target: target, level, "{}", message);
+    });
+}
+
+#[pyfunction]
+fn trace(message: &str) {
+    log_message(Level::Trace, message);
+}
+
+#[pyfunction]
+fn debug(message: &str) {
+    log_message(Level::Debug, message);
+}
+
+#[pyfunction]
+fn info(message: &str) {
+    log_message(Level::Info, message);
+}
+
+#[pyfunction]
+fn warning(message: &str) {
+    log_message(Level::Warn, message);
+}
+
+#[pyfunction]
+fn error(message: &str) {
+    log_message(Level::Error, message);
+}
+
+#[pymodule]
+fn mechsync(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Message>()?;
+    m.add_function(wrap_pyfunction!(time, m)?)?;
+    m.add_function(wrap_pyfunction!(tempo, m)?)?;
+    m.add_function(wrap_pyfunction!(schedule, m)?)?;
+    m.add_function(wrap_pyfunction!(trace, m)?)?;
+    m.add_function(wrap_pyfunction!(debug, m)?)?;
+    m.add_function(wrap_pyfunction!(info, m)?)?;
+    m.add_function(wrap_pyfunction!(warning, m)?)?;
+    m.add_function(wrap_pyfunction!(error, m)?)?;
+    Ok(())
+}
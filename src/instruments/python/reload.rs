@@ -0,0 +1,38 @@
+use std::fs::metadata;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use log::error;
+use crate::instruments::PyNode;
+
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl PyNode {
+    // polls the node's source file, reloading it on change for as long as the node is alive
+    pub(crate) fn watch(node: &Arc<PyNode>) {
+        let weak = Arc::downgrade(node);
+        let path = node.path.clone();
+        let mut last_modified = modified(&path);
+        let spawned = thread::Builder::new()
+            .name(format!("{} reload", node.name))
+            .spawn(move || loop {
+                thread::sleep(RELOAD_INTERVAL);
+                let Some(node) = weak.upgrade() else {
+                    return;
+                };
+                let current = modified(&path);
+                if current.is_some() && current != last_modified {
+                    last_modified = current;
+                    node.reload();
+                }
+            });
+        if let Err(err) = spawned {
+            error!(target: "PyNode", "Unable to watch {}: {}", node.path.display(), err);
+        }
+    }
+}
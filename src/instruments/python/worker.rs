@@ -0,0 +1,149 @@
+use std::os::raw::{c_long, c_ulong};
+use std::ptr::null_mut;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use log::{error, warn};
+use pyo3::{ffi, Python};
+
+extern "C" {
+    fn PyThread_get_thread_ident() -> c_ulong;
+}
+
+enum TaskState {
+    Pending,
+    Running(c_long),
+    Cancelled,
+    // interrupted while running, so that its worker has been replaced and exits once the task returns
+    Abandoned,
+    Done
+}
+
+struct Task {
+    state: Arc<Mutex<TaskState>>,
+    run: Box<dyn FnOnce(Python<'_>) + Send>
+}
+
+// a handle on a submitted task, allowing it to be cancelled or interrupted once it has overrun
+pub(super) struct TaskHandle {
+    state: Arc<Mutex<TaskState>>
+}
+
+// python is executed on dedicated threads, so that a slow script cannot stall the coroutines sharing the GIL
+pub(super) struct Pool {
+    name: String,
+    sender: Mutex<Sender<Task>>,
+    receiver: Arc<Mutex<Receiver<Task>>>,
+    // every worker started, including replacements, so that each is named distinctly
+    started: AtomicUsize
+}
+
+impl Pool {
+    pub(super) fn new(name: &str, workers: usize) -> Self {
+        let (sender, receiver) = channel::<Task>();
+        let pool = Pool {
+            name: String::from(name),
+            sender: Mutex::new(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            started: AtomicUsize::new(0)
+        };
+        for _ in 0..workers.max(1) {
+            pool.spawn();
+        }
+        pool
+    }
+
+    fn spawn(&self) {
+        let index = self.started.fetch_add(1, Ordering::Relaxed);
+        let receiver = self.receiver.clone();
+        let spawned = thread::Builder::new()
+            .name(format!("{} worker {}", self.name, index))
+            .spawn(move || Pool::work(receiver));
+        if let Err(err) = spawned {
+            error!(target: "PyNode", "Unable to start worker for {}: {}", self.name, err);
+        }
+    }
+
+    pub(super) fn submit(&self, run: impl FnOnce(Python<'_>) + Send + 'static) -> TaskHandle {
+        let state = Arc::new(Mutex::new(TaskState::Pending));
+        let task = Task { state: state.clone(), run: Box::new(run) };
+        if self.sender.lock().unwrap().send(task).is_err() {
+            warn!(target: "PyNode", "No workers available, dropping task");
+        }
+        TaskHandle { state }
+    }
+
+    // cancels the task if it's still queued, or otherwise raises a TimeoutError within it. As a script may catch the
+    // TimeoutError and carry on, its worker is abandoned and replaced, keeping the pool at its size
+    pub(super) fn interrupt(&self, task: &TaskHandle) {
+        let mut state = task.state.lock().unwrap();
+        match *state {
+            TaskState::Pending => *state = TaskState::Cancelled,
+            TaskState::Running(ident) => {
+                Python::with_gil(|_py| unsafe {
+                    ffi::PyThreadState_SetAsyncExc(ident, ffi::PyExc_TimeoutError);
+                });
+                *state = TaskState::Abandoned;
+                drop(state);
+                warn!(target: "PyNode", "Abandoning a worker of {} and starting a replacement", self.name);
+                self.spawn();
+            }
+            TaskState::Cancelled | TaskState::Abandoned | TaskState::Done => {}
+        }
+    }
+
+    fn work(receiver: Arc<Mutex<Receiver<Task>>>) {
+        let ident = unsafe { PyThread_get_thread_ident() } as c_long;
+        loop {
+            let Ok(task) = receiver.lock().unwrap().recv() else {
+                return;
+            };
+            {
+                let mut state = task.state.lock().unwrap();
+                if let TaskState::Cancelled = *state {
+                    continue;
+                }
+                *state = TaskState::Running(ident);
+            }
+            Python::with_gil(|py| {
+                // an interrupt may have arrived after the previous task completed, which must not leak into this one
+                unsafe {
+                    ffi::PyThreadState_SetAsyncExc(ident, null_mut());
+                }
+                (task.run)(py);
+            });
+            let mut state = task.state.lock().unwrap();
+            if let TaskState::Abandoned = *state {
+                return;
+            }
+            *state = TaskState::Done;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn interrupted_workers_are_replaced() {
+        pyo3::prepare_freethreaded_python();
+        let pool = Pool::new("test", 1);
+        // a script which catches the TimeoutError keeps its worker busy for good
+        let stubborn = pool.submit(|py| {
+            let script = "import time\nwhile True:\n    try:\n        time.sleep(0.01)\n    except TimeoutError:\n        pass";
+            let _ = py.run_bound(script, None, None);
+        });
+        thread::sleep(Duration::from_millis(200));
+        pool.interrupt(&stubborn);
+
+        let (sender, receiver) = channel();
+        pool.submit(move |_py| {
+            let _ = sender.send(());
+        });
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
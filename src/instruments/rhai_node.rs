@@ -0,0 +1,244 @@
+use std::sync::Weak;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, info};
+use may::sync::mpsc::{channel, Receiver, Sender};
+use may::sync::{Mutex, RwLock};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST, INT};
+use serde::Deserialize;
+use serde_yml::Value;
+use crate::data::MidiData;
+use crate::node::{dispatch, Node, OptNode};
+use crate::transforms::clock;
+
+// limits applied to every script, on top of the configurable operation budget
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_STRING_SIZE: usize = 4096;
+const MAX_COLLECTION_SIZE: usize = 1024;
+
+// in bytes, as evaluation recurses deeper than a coroutine's stack allows
+const STACK_SIZE: usize = 0x400000;
+
+type Messages = Result<Vec<(MidiData, f32)>, Box<EvalAltResult>>;
+type Call = ((INT, INT, INT, INT), Sender<Messages>);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RhaiNodeOptions {
+    pub(crate) source: String,
+    pub(crate) duration: f32,
+    pub(crate) params: Option<serde_yml::Value>,
+    #[serde(default = "default_max_operations")]
+    pub(crate) max_operations: u64
+}
+
+fn default_max_operations() -> u64 {
+    100_000
+}
+
+pub(crate) struct RhaiNode {
+    name: String,
+    duration: Duration,
+    // scripts are evaluated on the node's own thread, which exits once the node is dropped
+    calls: Mutex<Sender<Call>>,
+    next: OptNode
+}
+
+impl RhaiNode {
+    pub(crate) fn new(
+        name: &str,
+        source: &str,
+        duration: Duration,
+        params: Option<&Value>,
+        max_operations: u64
+    ) -> Result<Self, Box<EvalAltResult>> {
+        let (calls, receiver) = channel();
+        let (loaded, load) = channel();
+        let (script_name, source, params) = (String::from(name), String::from(source), params.cloned());
+        thread::Builder::new()
+            .name(format!("{} script", name))
+            .stack_size(STACK_SIZE)
+            .spawn(move || match Script::new(&script_name, &source, params.as_ref(), max_operations) {
+                Ok(script) => {
+                    let _ = loaded.send(Ok(()));
+                    script.serve(receiver);
+                }
+                Err(err) => {
+                    let _ = loaded.send(Err(err));
+                }
+            })
+            .map_err(|err| format!("Unable to start script thread: {}", err))?;
+        load.recv().map_err(|_| "Script thread exited while loading")??;
+
+        Ok(RhaiNode {
+            name: String::from(name),
+            duration,
+            calls: Mutex::new(calls),
+            next: RwLock::new(None)
+        })
+    }
+}
+
+struct Script {
+    engine: Engine,
+    ast: AST,
+    // persists between calls, and is exposed to the script's functions as `this`
+    state: Dynamic
+}
+
+impl Script {
+    fn new(name: &str, source: &str, params: Option<&Value>, max_operations: u64) -> Result<Self, Box<EvalAltResult>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(max_operations)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            // scripts are sandboxed, so may not import other files
+            .set_module_resolver(DummyModuleResolver::new());
+
+        let print_target = String::from(name);
+        engine.on_print(move |text| info!(target: &print_target, "{}", text));
+        let debug_target = String::from(name);
+        engine.on_debug(move |text, _source, _pos| debug!(target: &debug_target, "{}", text));
+        // the tempo followed by the most recently ticking Clock node, or unit when there is none
+        engine.register_fn("tempo", || clock::tempo().map_or(Dynamic::UNIT, Dynamic::from_float));
+
+        let ast = engine.compile(source)?;
+        if !has_function(&ast, "process", 4) {
+            return Err("Script must define fn process(instruction, channel, note, velocity)".into());
+        }
+
+        let mut state = Dynamic::from_map(Map::new());
+        if has_function(&ast, "init", 1) {
+            let params = match params {
+                Some(params) => rhai::serde::to_dynamic(params)?,
+                None => Dynamic::from_map(Map::new())
+            };
+            let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut state);
+            let _ = engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &ast, "init", (params,))?;
+        }
+        Ok(Script { engine, ast, state })
+    }
+
+    fn serve(mut self, calls: Receiver<Call>) {
+        while let Ok((args, reply)) = calls.recv() {
+            let _ = reply.send(self.process(args));
+        }
+    }
+
+    fn process(&mut self, args: (INT, INT, INT, INT)) -> Messages {
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, "process", args)
+            .and_then(extract_messages)
+    }
+}
+
+fn has_function(ast: &AST, name: &str, params: usize) -> bool {
+    ast.iter_functions().any(|f| f.name == name && f.params.len() == params)
+}
+
+// scripts may return nothing, a single message, or an array of messages, where messages are either
+// [instruction, channel, note, velocity, delay] arrays (delay being optional) or maps with matching keys
+fn extract_messages(out: Dynamic) -> Result<Vec<(MidiData, f32)>, Box<EvalAltResult>> {
+    if out.is_unit() {
+        return Ok(Vec::new());
+    }
+    if out.is_array() {
+        let array = out.into_array()?;
+        // arrays may either hold the fields of a single message, or several messages
+        if array.first().map(Dynamic::is_int).unwrap_or(false) {
+            return Ok(vec![extract_fields(&array)?]);
+        }
+        return array.into_iter().map(extract_message).collect();
+    }
+    Ok(vec![extract_message(out)?])
+}
+
+fn extract_message(out: Dynamic) -> Result<(MidiData, f32), Box<EvalAltResult>> {
+    if out.is_array() {
+        return extract_fields(&out.into_array()?);
+    }
+    let map = out.try_cast::<Map>().ok_or("Expected a message array or map")?;
+    let field = |key: &str| map.get(key).ok_or(format!("Message is missing {}", key));
+    Ok((MidiData {
+        instruction: to_byte(field("instruction")?)?,
+        channel: to_byte(field("channel")?)?,
+        note: to_byte(field("note")?)?,
+        velocity: to_byte(field("velocity")?)?,
+    }, map.get("delay").map(to_delay).transpose()?.unwrap_or_default()))
+}
+
+fn extract_fields(array: &Array) -> Result<(MidiData, f32), Box<EvalAltResult>> {
+    let [instruction, channel, note, velocity, rest @ ..] = array.as_slice() else {
+        return Err(format!("Expected at least 4 message fields, got {}", array.len()).into());
+    };
+    Ok((MidiData {
+        instruction: to_byte(instruction)?,
+        channel: to_byte(channel)?,
+        note: to_byte(note)?,
+        velocity: to_byte(velocity)?,
+    }, rest.first().map(to_delay).transpose()?.unwrap_or_default()))
+}
+
+fn to_byte(value: &Dynamic) -> Result<u8, Box<EvalAltResult>> {
+    let int = value.as_int()?;
+    u8::try_from(int).map_err(|_| format!("{} is out of range for a MIDI byte", int).into())
+}
+
+fn to_delay(value: &Dynamic) -> Result<f32, Box<EvalAltResult>> {
+    value.as_float()
+        .map(|float| float as f32)
+        .or_else(|_| value.as_int().map(|int| int as f32))
+        .map_err(|type_name| format!("Expected a numeric delay, got {}", type_name).into())
+}
+
+impl Node for RhaiNode {
+    fn call(&self, data: MidiData) {
+        let ts_start = Instant::now();
+        let (reply, output) = channel();
+        let args = (data.instruction as INT, data.channel as INT, data.note as INT, data.velocity as INT);
+        if self.calls.lock().unwrap().send((args, reply)).is_err() {
+            error!(target: &self.name, "Script thread has stopped");
+            return;
+        }
+        match output.recv() {
+            Ok(Ok(messages)) => dispatch(&self.name, &self.next, ts_start, self.duration, messages),
+            Ok(Err(error)) => error!(target: &self.name, "Script failed due to error: {}", error),
+            Err(_) => error!(target: &self.name, "Script thread has stopped")
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use may::go;
+    use super::*;
+
+    #[test]
+    fn scripts_may_not_import_files() {
+        let source = "fn process(instruction, channel, note, velocity) { import \"Cargo\" as cargo; }";
+        let mut script = Script::new("test", source, None, 1000).unwrap();
+        assert!(script.process((9, 0, 40, 100)).is_err());
+    }
+
+    #[test]
+    fn scripts_recurse_within_coroutines() {
+        let source = r#"
+            fn depth(n) { if n == 0 { 0 } else { 1 + depth(n - 1) } }
+            fn init(params) { this.depth = depth(30); }
+            fn process(instruction, channel, note, velocity) { [instruction, channel, note, this.depth] }
+        "#;
+        let loaded = go!(move || RhaiNode::new("test", source, Duration::ZERO, None, 100_000).is_ok()).join();
+        assert!(loaded.unwrap());
+    }
+}
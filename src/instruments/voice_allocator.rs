@@ -0,0 +1,369 @@
+use std::collections::HashSet;
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use may::coroutine::sleep;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::config::StringMovement;
+use crate::data::{MidiData, NOTE_OFF};
+use crate::instruments::allocator::{Allocator, Player};
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VoiceAllocatorOptions {
+    pub(crate) voices: Vec<VoiceConfig>,
+    // applies to every voice without its own
+    #[serde(default)]
+    pub(crate) movement: MovementConfig,
+    #[serde(default)]
+    pub(crate) steal: StealPolicy
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct VoiceConfig {
+    pub(crate) name: Option<String>,
+    // the notes the voice can play, either as an inclusive range, a list, or both
+    pub(crate) range: Option<(u8, u8)>,
+    #[serde(default)]
+    pub(crate) notes: Vec<u8>,
+    // defaults to the voice's index
+    pub(crate) channel: Option<u8>,
+    pub(crate) movement: Option<MovementConfig>
+}
+
+// the seconds taken for a voice to move between notes, as the sum of each term
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MovementConfig {
+    // for any change of note
+    #[serde(default)]
+    pub(crate) fixed: f32,
+    #[serde(default)]
+    pub(crate) per_semitone: f32,
+    // a regression over the distance travelled along a string, as a fraction of its length from the lowest note
+    pub(crate) string: Option<StringMovement>
+}
+
+// which sounding note is cut short when every voice able to play a note is busy
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StealPolicy {
+    #[default]
+    Oldest,
+    Quietest,
+    // the voice which would move the least
+    Closest
+}
+
+struct Movement {
+    config: MovementConfig,
+    // the note string distances are measured from
+    lowest: u8
+}
+
+impl Movement {
+    fn time(&self, from: u8, to: u8) -> Duration {
+        if from == to {
+            return Duration::ZERO;
+        }
+        let time = self.config.fixed + self.config.per_semitone * from.abs_diff(to) as f32;
+        let string = self.config.string
+            .map(|string| string.time(from.saturating_sub(self.lowest), to.saturating_sub(self.lowest)))
+            .unwrap_or_default();
+        Duration::from_secs_f32(time.max(0f32)) + string
+    }
+}
+
+struct Sounding {
+    // the channel and note the note-on was received as
+    input: (u8, u8),
+    generation: u64,
+    velocity: u8,
+    delay: Duration,
+    ts: Instant
+}
+
+struct Voice {
+    name: String,
+    notes: Vec<u8>,
+    channel: u8,
+    movement: Movement,
+    // the note the voice is positioned at, and any note it is sounding
+    position: u8,
+    sounding: Option<Sounding>,
+    // incremented for every note the voice is given, identifying each of them
+    generation: u64,
+    // the generations of note-ons waiting out their delay, which are cancelled should the voice be stolen meanwhile
+    pending: Vec<u64>
+}
+
+impl Voice {
+    fn from_config(index: usize, config: &VoiceConfig, movement: &MovementConfig) -> Self {
+        let mut notes = config.notes.clone();
+        if let Some((low, high)) = config.range {
+            notes.extend(low..=high);
+        }
+        notes.sort_unstable();
+        notes.dedup();
+        let lowest = notes.first().copied().unwrap_or_default();
+        Voice {
+            name: config.name.clone().unwrap_or_else(|| format!("voice {}", index)),
+            notes,
+            channel: config.channel.unwrap_or(index as u8),
+            movement: Movement { config: config.movement.clone().unwrap_or_else(|| movement.clone()), lowest },
+            position: lowest,
+            sounding: None,
+            generation: 0,
+            pending: Vec::new()
+        }
+    }
+
+    // the longest the voice may take to move between any of its notes
+    fn max_time(&self) -> Duration {
+        match (self.notes.first(), self.notes.last()) {
+            (Some(&low), Some(&high)) => self.movement.time(low, high),
+            _ => Duration::ZERO
+        }
+    }
+}
+
+// voices are free while silent, and cost the seconds they would take to move
+impl Player for Voice {
+    fn plays(&self, note: u8) -> bool {
+        self.notes.binary_search(&note).is_ok()
+    }
+
+    fn free(&self, _note: u8, _at: Instant) -> bool {
+        self.sounding.is_none()
+    }
+
+    fn cost(&self, note: u8) -> f32 {
+        self.movement.time(self.position, note).as_secs_f32()
+    }
+
+    fn last(&self) -> Option<Instant> {
+        self.sounding.as_ref().map(|sounding| sounding.ts)
+    }
+}
+
+// assigns notes to whichever voice can play them soonest, delaying each by the time the slowest movement would take
+// less its own, such that every note is sent with the same latency
+pub(crate) struct VoiceAllocator {
+    name: String,
+    voices: Mutex<Allocator<Voice>>,
+    steal: StealPolicy,
+    max_time: Duration,
+    // notes no voice could play, whose note-offs are passed through alongside them
+    passed: Mutex<HashSet<(u8, u8)>>,
+    next: OptNode
+}
+
+impl VoiceAllocator {
+    pub(crate) fn new(name: &str, voices: &[VoiceConfig], movement: &MovementConfig, steal: StealPolicy) -> Self {
+        let voices: Vec<Voice> = voices.iter()
+            .enumerate()
+            .map(|(index, config)| Voice::from_config(index, config, movement))
+            .collect();
+        VoiceAllocator {
+            name: String::from(name),
+            max_time: voices.iter().map(Voice::max_time).max().unwrap_or_default(),
+            voices: Mutex::new(Allocator::new(voices)),
+            steal,
+            passed: Mutex::new(HashSet::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    // the voice to play the note on, preferring idle voices which would move the least, and otherwise stealing one
+    fn choose(&self, voices: &Allocator<Voice>, note: u8) -> Option<usize> {
+        let chosen = voices.choose(note, Instant::now())?;
+        if chosen.free {
+            return Some(chosen.index);
+        }
+        let candidates = voices.candidates(note, Instant::now()).into_iter();
+        let sounding = |index: usize| voices[index].sounding.as_ref();
+        let stolen = match self.steal {
+            StealPolicy::Oldest => candidates.min_by_key(|candidate| sounding(candidate.index).map(|sounding| sounding.ts)),
+            StealPolicy::Quietest => candidates.min_by_key(|candidate| sounding(candidate.index).map(|sounding| sounding.velocity)),
+            StealPolicy::Closest => Some(chosen)
+        };
+        stolen.map(|candidate| candidate.index)
+    }
+
+    fn note_on(&self, data: MidiData) {
+        let (index, generation, out, stolen, delay) = {
+            let mut voices = self.voices.lock().unwrap();
+            let Some(index) = self.choose(&voices, data.note) else {
+                warn!(target: &self.name, "No voices able to play {}, performing direct pass-through!", data.note);
+                drop(voices);
+                self.passed.lock().unwrap().insert((data.channel, data.note));
+                self.next.call(data);
+                return;
+            };
+            let voice = &mut voices[index];
+            let mut stolen = None;
+            if let Some(sounding) = voice.sounding.take() {
+                warn!(target: &self.name, "Note {} overriden by {} on {}", voice.position, data.note, voice.name);
+                // stolen notes yet to sound are cancelled instead of released
+                let pending = voice.pending.iter().position(|generation| *generation == sounding.generation);
+                match pending {
+                    Some(pending) => {
+                        voice.pending.swap_remove(pending);
+                    }
+                    None => {
+                        let note = voice.position;
+                        stolen = Some(MidiData { instruction: NOTE_OFF, channel: voice.channel, note, velocity: 0 });
+                    }
+                }
+            }
+            let delay = self.max_time.saturating_sub(voice.movement.time(voice.position, data.note));
+            voice.generation += 1;
+            voice.position = data.note;
+            voice.sounding = Some(Sounding {
+                input: (data.channel, data.note),
+                generation: voice.generation,
+                velocity: data.velocity,
+                delay,
+                ts: Instant::now()
+            });
+            voice.pending.push(voice.generation);
+            info!(target: &self.name, "⬇{} on {}", data.note, voice.name);
+            (index, voice.generation, MidiData { channel: voice.channel, ..data }, stolen, delay)
+        };
+
+        if let Some(stolen) = stolen {
+            self.next.call(stolen);
+        }
+        sleep(delay);
+        // notes released before they sounded are still sent, followed by their note-off
+        let cancelled = {
+            let mut voices = self.voices.lock().unwrap();
+            let pending = &mut voices[index].pending;
+            match pending.iter().position(|pending| *pending == generation) {
+                Some(position) => {
+                    pending.swap_remove(position);
+                    false
+                }
+                None => true
+            }
+        };
+        if cancelled {
+            debug!(target: &self.name, "Dropping {:?}, as its voice was stolen before it sounded", data);
+        } else {
+            self.next.call(out);
+        }
+    }
+
+    fn note_off(&self, data: MidiData) {
+        let (out, delay) = {
+            let mut voices = self.voices.lock().unwrap();
+            let voice = voices.iter_mut()
+                .find(|voice| voice.sounding.as_ref().is_some_and(|sounding| sounding.input == (data.channel, data.note)));
+            let Some(voice) = voice else {
+                if self.passed.lock().unwrap().remove(&(data.channel, data.note)) {
+                    drop(voices);
+                    self.next.call(data);
+                } else {
+                    debug!(target: &self.name, "Released note {}, but none were playing", data.note);
+                }
+                return;
+            };
+            let delay = voice.sounding.take().map(|sounding| sounding.delay).unwrap_or_default();
+            info!(target: &self.name, "⬆{} on {}", data.note, voice.name);
+            (MidiData { channel: voice.channel, ..data }, delay)
+        };
+        // sent after the same delay as the note-on, preserving the note's length
+        sleep(delay);
+        self.next.call(out);
+    }
+}
+
+impl Node for VoiceAllocator {
+    fn call(&self, data: MidiData) {
+        if data.is_note_on() {
+            self.note_on(data);
+        } else if data.is_note_off() {
+            self.note_off(data);
+        } else {
+            self.next.call(data);
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.max_time
+    }
+
+    fn state(&self) -> Value {
+        Value::Array(self.voices.lock().unwrap().iter().map(|voice| json!({
+            "name": voice.name,
+            "note": voice.position,
+            "playing": voice.sounding.is_some()
+        })).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread::{sleep, spawn};
+    use crate::data::NOTE_ON;
+    use crate::node::testing::Recorder;
+    use super::*;
+
+    fn message(instruction: u8, note: u8) -> MidiData {
+        MidiData { instruction, channel: 0, note, velocity: if instruction == NOTE_ON { 100 } else { 0 } }
+    }
+
+    // a single voice, taking 50ms to move between its notes
+    fn allocator(notes: (u8, u8)) -> Arc<VoiceAllocator> {
+        let voice = VoiceConfig { name: None, range: Some(notes), notes: Vec::new(), channel: None, movement: None };
+        let movement = MovementConfig { fixed: 0.05, per_semitone: 0.0, string: None };
+        Arc::new(VoiceAllocator::new("VoiceAllocator", &[voice], &movement, StealPolicy::Oldest))
+    }
+
+    // sends each message on its own thread, as they would be sent by MIDI input, a few milliseconds apart
+    fn play(allocator: &Arc<VoiceAllocator>, messages: &[MidiData]) {
+        let threads: Vec<_> = messages.iter().copied().map(|data| {
+            let allocator = allocator.clone();
+            let thread = spawn(move || allocator.call(data));
+            sleep(Duration::from_millis(10));
+            thread
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn notes_no_voice_can_play_pass_through_with_their_note_off() {
+        let allocator = allocator((40, 50));
+        let recorder = Recorder::after(allocator.as_ref());
+        play(&allocator, &[message(NOTE_ON, 60), message(NOTE_OFF, 60), message(NOTE_OFF, 60)]);
+        assert_eq!(recorder.take(), [message(NOTE_ON, 60), message(NOTE_OFF, 60)]);
+    }
+
+    #[test]
+    fn notes_released_before_sounding_are_still_sent() {
+        let allocator = allocator((40, 50));
+        let recorder = Recorder::after(allocator.as_ref());
+        play(&allocator, &[message(NOTE_ON, 40), message(NOTE_OFF, 40), message(NOTE_ON, 40)]);
+        assert_eq!(recorder.take(), [message(NOTE_ON, 40), message(NOTE_OFF, 40), message(NOTE_ON, 40)]);
+    }
+
+    #[test]
+    fn stolen_notes_yet_to_sound_are_cancelled() {
+        let allocator = allocator((40, 41));
+        let recorder = Recorder::after(allocator.as_ref());
+        // the first note waits out the whole 50ms, as the voice is already in place, whereas the second moves
+        play(&allocator, &[message(NOTE_ON, 40), message(NOTE_ON, 41), message(NOTE_OFF, 40), message(NOTE_OFF, 41)]);
+        assert_eq!(recorder.take(), [message(NOTE_ON, 41), message(NOTE_OFF, 41)]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::Weak;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_yml::Value;
+use wasmi::{Caller, Config, Engine, Error, Extern, Linker, Memory, Module, Store, TypedFunc};
+use crate::data::MidiData;
+use crate::node::{dispatch, Node, OptNode};
+
+// each output record is [instruction, channel, note, velocity] followed by the delay as a little-endian f32
+const RECORD_SIZE: usize = 8;
+const MAX_MESSAGES: usize = 256;
+
+struct Runtime {
+    store: Store<String>,
+    memory: Memory,
+    process: TypedFunc<(i32, i32, i32, i32), i32>,
+    output: TypedFunc<(), i32>
+}
+
+impl Runtime {
+    fn process(&mut self, data: &MidiData, fuel: u64) -> Result<Vec<(MidiData, f32)>, Error> {
+        self.store.set_fuel(fuel)?;
+        let count = self.process.call(&mut self.store, (
+            i32::from(data.instruction),
+            i32::from(data.channel),
+            i32::from(data.note),
+            i32::from(data.velocity)
+        ))?;
+        let count = usize::try_from(count).unwrap_or_default();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        if count > MAX_MESSAGES {
+            return Err(Error::new(format!("process returned {} messages, at most {} are allowed", count, MAX_MESSAGES)));
+        }
+
+        let ptr = self.output.call(&mut self.store, ())?;
+        let mut buffer = vec![0u8; count * RECORD_SIZE];
+        self.memory.read(&self.store, ptr as u32 as usize, &mut buffer)?;
+        Ok(buffer.chunks_exact(RECORD_SIZE).map(|record| (MidiData {
+            instruction: record[0],
+            channel: record[1],
+            note: record[2],
+            velocity: record[3],
+        }, f32::from_le_bytes([record[4], record[5], record[6], record[7]]))).collect())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WasmNodeOptions {
+    pub(crate) source: String,
+    pub(crate) duration: f32,
+    pub(crate) params: Option<serde_yml::Value>,
+    #[serde(default = "default_fuel")]
+    pub(crate) fuel: u64
+}
+
+fn default_fuel() -> u64 {
+    1_000_000
+}
+
+// modules must export `memory`, `process(instruction, channel, note, velocity) -> count` and `output() -> ptr`,
+// and may export `init(ptr, len)` alongside `alloc(len) -> ptr` to receive their params as JSON
+pub(crate) struct WasmNode {
+    name: String,
+    duration: Duration,
+    fuel: u64,
+    runtime: Mutex<Runtime>,
+    next: OptNode
+}
+
+impl WasmNode {
+    pub(crate) fn new(
+        name: &str,
+        wasm: &[u8],
+        duration: Duration,
+        params: Option<&Value>,
+        fuel: u64
+    ) -> Result<Self, Error> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm)?;
+
+        // the only capability granted to modules is logging through the node
+        let mut store = Store::new(&engine, String::from(name));
+        let mut linker = Linker::<String>::new(&engine);
+        linker.func_wrap("mechsync", "log", |caller: Caller<'_, String>, ptr: i32, len: i32| {
+            let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+                return;
+            };
+            let (start, len) = (ptr as u32 as usize, len as u32 as usize);
+            if let Some(bytes) = memory.data(&caller).get(start..start.saturating_add(len)) {
+                info!(target: caller.data(), "{}", String::from_utf8_lossy(bytes));
+            }
+        })?;
+
+        store.set_fuel(fuel)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or(Error::new("Module must export memory"))?;
+        let process = instance.get_typed_func::<(i32, i32, i32, i32), i32>(&store, "process")?;
+        let output = instance.get_typed_func::<(), i32>(&store, "output")?;
+
+        let init = match instance.get_func(&store, "init") {
+            Some(_init) => Some(instance.get_typed_func::<(i32, i32), ()>(&store, "init")?),
+            None => None
+        };
+        // params would otherwise be silently ignored
+        let has_params = params.is_some_and(|params| match params {
+            Value::Null => false,
+            Value::Mapping(mapping) => !mapping.is_empty(),
+            Value::Sequence(sequence) => !sequence.is_empty(),
+            _ => true
+        });
+        if init.is_none() && has_params {
+            return Err(Error::new("Module must export init to be given params"));
+        }
+
+        if let Some(init) = init {
+            let bytes = match params {
+                Some(params) => serde_json::to_vec(params).map_err(|err| Error::new(err.to_string()))?,
+                None => Vec::new()
+            };
+            let len = i32::try_from(bytes.len()).map_err(|_| Error::new("Params are too large"))?;
+            let ptr = if bytes.is_empty() {
+                0
+            } else {
+                let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+                store.set_fuel(fuel)?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, &bytes)?;
+                ptr
+            };
+            store.set_fuel(fuel)?;
+            init.call(&mut store, (ptr, len))?;
+        }
+
+        Ok(WasmNode {
+            name: String::from(name),
+            duration,
+            fuel,
+            runtime: Mutex::new(Runtime { store, memory, process, output }),
+            next: RwLock::new(None)
+        })
+    }
+}
+
+impl Node for WasmNode {
+    fn call(&self, data: MidiData) {
+        let ts_start = Instant::now();
+        let output = self.runtime.lock().unwrap().process(&data, self.fuel);
+        match output {
+            Ok(messages) => dispatch(&self.name, &self.next, ts_start, self.duration, messages),
+            Err(error) => error!(target: &self.name, "Module failed due to error: {}", error)
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &str = r#"
+        (memory (export "memory") 1)
+        (func (export "output") (result i32) (i32.const 0))
+        (func (export "process") (param i32 i32 i32 i32) (result i32) (i32.const 0))
+    "#;
+
+    fn node(exports: &str, params: &str) -> Result<WasmNode, Error> {
+        let wasm = wat::parse_str(format!("(module {} {})", MODULE, exports)).unwrap();
+        let params: Value = serde_yml::from_str(params).unwrap();
+        WasmNode::new("test", &wasm, Duration::ZERO, Some(&params), 10_000)
+    }
+
+    #[test]
+    fn params_require_init() {
+        assert!(node("", "{ interval: 7 }").is_err());
+        assert!(node("", "{}").is_ok());
+        let init = r#"
+            (func (export "alloc") (param i32) (result i32) (i32.const 64))
+            (func (export "init") (param i32 i32))
+        "#;
+        assert!(node(init, "{ interval: 7 }").is_ok());
+    }
+}
@@ -0,0 +1,8 @@
+pub mod node;
+pub mod data;
+pub mod config;
+pub mod cli;
+mod control;
+mod midi;
+mod instruments;
+mod transforms;
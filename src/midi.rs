@@ -58,9 +58,11 @@ impl Input {
 }
 
 impl Node for Input {
-    // NOTE: you probably didn't want to call this
-    fn call(&self, _data: MidiData) -> () {
-        unimplemented!()
+    // treats the message as though it were received, allowing messages to be injected
+    fn call(&self, data: MidiData) {
+        unsafe {
+            self.binding.call(data);
+        }
     }
 
     fn bind(&self, node: Weak<dyn Node>) {
@@ -95,13 +97,13 @@ impl Output {
 }
 
 impl Node for Output {
-    fn call(&self, data: MidiData) -> () {
+    fn call(&self, data: MidiData) {
         trace!(target: &self.name, "Transmitting {:?}", data);
-        self.output.lock().unwrap().send(data.to_array().as_slice()).unwrap();
+        self.output.lock().unwrap().send(&data.to_array()[..data.size()]).unwrap();
     }
 
     // NOTE: you probably didn't want to call this
-    fn bind(&self, _node: Weak<dyn Node>) -> () {
+    fn bind(&self, _node: Weak<dyn Node>) {
         unimplemented!()
     }
 }
\ No newline at end of file
@@ -1,30 +1,98 @@
-use std::sync::Weak;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
-use log::debug;
+use log::{debug, info, warn};
 use may::coroutine::sleep;
+use may::go;
 use may::sync::RwLock;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::config::Arrivals;
 use crate::data::MidiData;
 
-pub(crate) type OptNode = RwLock<Option<Weak<dyn Node>>>;
+pub type OptNode = RwLock<Option<Weak<dyn Node>>>;
 
-pub(crate) trait Node: Sync + Send {
-    fn call(&self, data: MidiData) -> ();
+// sends each message `duration + delay` after `start`, each on its own coroutine so that a successor which sleeps only
+// holds back the message it was given. Negative delays are sent straight away, and those which aren't a valid
+// duration (such as NaN, or returned by a faulty script) are dropped
+pub fn dispatch(target: &str, next: &OptNode, start: Instant, duration: Duration, messages: Vec<(MidiData, f32)>) {
+    let Some(next) = next.read().unwrap().clone() else {
+        return;
+    };
+    for (data, delay) in messages {
+        let offset = if delay.is_finite() && delay < 0f32 { Ok(Duration::ZERO) } else { Duration::try_from_secs_f32(delay) };
+        let Some(target_duration) = offset.ok().and_then(|offset| duration.checked_add(offset)) else {
+            warn!(target: target, "Dropping {:?}, as its delay of {} isn't a valid duration", data, delay);
+            continue;
+        };
+        let Some(due) = start.checked_add(target_duration) else {
+            warn!(target: target, "Dropping {:?}, as its delay of {} is too long", data, delay);
+            continue;
+        };
+        let target = String::from(target);
+        let next = next.clone();
+        go!(move || {
+            let now = Instant::now();
+            if now <= due {
+                sleep(due - now);
+            } else {
+                warn!(target: &target, "Took longer than {:?} (was {:?})", target_duration, now - start);
+            }
+            info!(target: &target, "Sending {:?}", data);
+            if let Some(node) = next.upgrade() {
+                node.call(data);
+            }
+        });
+    }
+}
+
+pub trait Node: Sync + Send {
+    fn call(&self, data: MidiData);
 
-    fn bind(&self, node: Weak<dyn Node>) -> ();
+    fn bind(&self, node: Weak<dyn Node>);
 
     fn delay(&self) -> Duration {
         Duration::from_secs(0)
     }
+
+    // the names of any nodes routed to besides `next`, which are each bound through bind_target
+    fn targets(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn bind_target(&self, _target: &str, _node: Weak<dyn Node>) {}
+
+    // the delay of messages sent on to the given successor or target, where it differs from the node's delay
+    fn delay_to(&self, _target: &str) -> Duration {
+        self.delay()
+    }
+
+    // a distinct entry point for messages from the given predecessor, used when binding it in place of the node itself
+    fn inlet(&self, _from: &str) -> Option<Arc<dyn Node>> {
+        None
+    }
+
+    // receives the arrivals of the complete graph once it is built, and again whenever delays change at runtime
+    fn resolve(&self, _name: &str, _arrivals: &Arrivals) {}
+
+    // runtime state reported through the control API
+    fn state(&self) -> Value {
+        Value::Null
+    }
+
+    // changes a parameter at runtime through the control API
+    fn set(&self, param: &str, _value: &Value) -> Result<(), String> {
+        Err(format!("Unknown parameter: {}", param))
+    }
 }
 
 impl Node for OptNode {
-    fn call(&self, data: MidiData) -> () {
+    fn call(&self, data: MidiData) {
         if let Some(wk_ref) = self.read().unwrap().as_ref().and_then(Weak::upgrade) {
             wk_ref.call(data);
         }
     }
 
-    fn bind(&self, node: Weak<dyn Node>) -> () {
+    fn bind(&self, node: Weak<dyn Node>) {
         let _ = self.write().unwrap().insert(node);
     }
 }
@@ -44,7 +112,7 @@ impl DebugNode {
 }
 
 impl Node for DebugNode {
-    fn call(&self, data: MidiData) -> () {
+    fn call(&self, data: MidiData) {
         debug!(target: &self.name, "Received {:?} at {:?}", data, Instant::now());
         self.next.call(data);
     }
@@ -54,32 +122,97 @@ impl Node for DebugNode {
     }
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DelayNodeOptions {
+    pub(crate) duration: f32,
+    // whether the duration is the total delay from the input, rather than that of this node alone
+    #[serde(default)]
+    pub(crate) is_total: bool
+}
+
 pub(crate) struct DelayNode {
-    duration: Duration,
+    duration: RwLock<Duration>,
     next: OptNode
 }
 
 impl DelayNode {
     pub(crate) fn new(duration: Duration) -> Self {
         DelayNode {
-            duration,
+            duration: RwLock::new(duration),
             next: RwLock::new(None),
         }
     }
 }
 
 impl Node for DelayNode {
-    fn call(&self, data: MidiData) -> () {
+    fn call(&self, data: MidiData) {
         //TODO: coroutine::sleep should be evaluated to see whether it may benefit from spin-locking
-        sleep(self.duration);
+        sleep(self.delay());
         self.next.call(data);
     }
 
-    fn bind(&self, node: Weak<dyn Node>) -> () {
+    fn bind(&self, node: Weak<dyn Node>) {
         self.next.bind(node);
     }
 
     fn delay(&self) -> Duration {
-        return self.duration
+        *self.duration.read().unwrap()
+    }
+
+    fn state(&self) -> Value {
+        json!({ "duration": self.delay().as_secs_f64() })
+    }
+
+    fn set(&self, param: &str, value: &Value) -> Result<(), String> {
+        match param {
+            "duration" => {
+                let duration = value.as_f64()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or(format!("Expected a duration in seconds, got {}", value))?;
+                *self.duration.write().unwrap() = duration;
+                Ok(())
+            }
+            param => Err(format!("Unknown parameter: {}", param))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::sync::{Arc, Mutex, Weak};
+    use crate::data::MidiData;
+    use crate::node::Node;
+
+    // collects every message it is sent, standing in as the successor of the node under test
+    pub(crate) struct Recorder {
+        messages: Mutex<Vec<MidiData>>
+    }
+
+    impl Recorder {
+        pub(crate) fn new() -> Arc<Recorder> {
+            Arc::new(Recorder { messages: Mutex::new(Vec::new()) })
+        }
+
+        // binds the given node to a new recorder
+        pub(crate) fn after(node: &dyn Node) -> Arc<Recorder> {
+            let recorder = Recorder::new();
+            let next: Arc<dyn Node> = recorder.clone();
+            node.bind(Arc::downgrade(&next));
+            recorder
+        }
+
+        // every message received since last taken
+        pub(crate) fn take(&self) -> Vec<MidiData> {
+            std::mem::take(&mut *self.messages.lock().unwrap())
+        }
+    }
+
+    impl Node for Recorder {
+        fn call(&self, data: MidiData) {
+            self.messages.lock().unwrap().push(data);
+        }
+
+        fn bind(&self, _node: Weak<dyn Node>) {}
     }
 }
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use may::coroutine::sleep;
+use may::go;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use crate::data::{MidiData, NOTE_OFF};
+use crate::node::{dispatch, Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArpeggiatorOptions {
+    pub(crate) mode: ChordMode,
+    // in seconds, within which notes are considered part of the same chord
+    #[serde(default = "default_window")]
+    pub(crate) window: f32,
+    // the order in which chords are strummed or arpeggiated
+    #[serde(default)]
+    pub(crate) pattern: ChordPattern,
+    // in seconds, between each note of a strum
+    pub(crate) spread: Option<f32>,
+    // in seconds, the length of each step of an arpeggio
+    pub(crate) rate: Option<f32>,
+    // the channels notes are split across
+    #[serde(default)]
+    pub(crate) channels: Vec<u8>
+}
+
+fn default_window() -> f32 {
+    0.02
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChordMode {
+    // plays the notes of a chord one after another
+    Strum,
+    // repeats the notes of a chord one at a time for as long as they are held
+    Arpeggiate,
+    // distributes notes across channels round-robin
+    Split
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChordPattern {
+    #[default]
+    Up,
+    Down,
+    UpDown,
+    // in the order the notes were received
+    Played
+}
+
+pub(crate) enum ChordStyle {
+    Strum { spread: Duration },
+    Arpeggiate { rate: Duration },
+    Split { channels: Vec<u8> }
+}
+
+// the sequence notes are played in, where up-down ascends then descends without repeating either end
+fn order(pattern: ChordPattern, notes: &[MidiData]) -> Vec<MidiData> {
+    let mut ordered = notes.to_vec();
+    match pattern {
+        ChordPattern::Up => ordered.sort_by_key(|data| data.note),
+        ChordPattern::Down => ordered.sort_by_key(|data| std::cmp::Reverse(data.note)),
+        ChordPattern::UpDown => {
+            ordered.sort_by_key(|data| data.note);
+            let descending: Vec<MidiData> = ordered.iter().rev().skip(1).take(ordered.len().saturating_sub(2)).copied().collect();
+            ordered.extend(descending);
+        }
+        ChordPattern::Played => {}
+    }
+    ordered
+}
+
+struct Chords {
+    // notes received within the current window
+    pending: Mutex<Vec<MidiData>>,
+    // note-ons held down, in the order they were received
+    held: Mutex<Vec<MidiData>>,
+    arpeggiating: AtomicBool,
+    // the channel each sounding note was sent on, and when it was due to start
+    sounding: Mutex<HashMap<(u8, u8), (u8, Instant)>>,
+    // the next channel to split onto, or whether to strum upwards when alternating
+    turn: AtomicUsize,
+    next: OptNode
+}
+
+// plays chords on monophonic instruments, by strumming, arpeggiating, or splitting them across channels
+pub(crate) struct Arpeggiator {
+    name: String,
+    window: Duration,
+    pattern: ChordPattern,
+    style: ChordStyle,
+    chords: Arc<Chords>
+}
+
+impl Arpeggiator {
+    pub(crate) fn new(name: &str, window: Duration, pattern: ChordPattern, style: ChordStyle) -> Self {
+        Arpeggiator {
+            name: String::from(name),
+            window,
+            pattern,
+            style,
+            chords: Arc::new(Chords {
+                pending: Mutex::new(Vec::new()),
+                held: Mutex::new(Vec::new()),
+                arpeggiating: AtomicBool::new(false),
+                sounding: Mutex::new(HashMap::new()),
+                turn: AtomicUsize::new(0),
+                next: RwLock::new(None)
+            })
+        }
+    }
+
+    fn strum(&self, batch: Vec<MidiData>, spread: Duration) {
+        let start = Instant::now();
+        let (ons, others): (Vec<MidiData>, Vec<MidiData>) = batch.into_iter().partition(MidiData::is_note_on);
+        let pattern = match self.pattern {
+            // alternates between down and up strokes
+            ChordPattern::UpDown if self.chords.turn.fetch_add(1, Ordering::Relaxed) % 2 == 1 => ChordPattern::Down,
+            ChordPattern::UpDown => ChordPattern::Up,
+            pattern => pattern
+        };
+
+        let mut messages = Vec::new();
+        let mut sounding = self.chords.sounding.lock().unwrap();
+        for (index, data) in order(pattern, &ons).into_iter().enumerate() {
+            let offset = spread * index as u32;
+            sounding.insert((data.channel, data.note), (data.channel, start + offset));
+            messages.push((data, offset.as_secs_f32()));
+        }
+        // note-offs wait for their note-on, should it still be due
+        for data in others {
+            let offset = match data.is_note_off() {
+                true => sounding.remove(&(data.channel, data.note))
+                    .map(|(_channel, due)| due.saturating_duration_since(start))
+                    .unwrap_or_default(),
+                false => Duration::ZERO
+            };
+            messages.push((data, offset.as_secs_f32()));
+        }
+        drop(sounding);
+        dispatch(&self.name, &self.chords.next, start, Duration::ZERO, messages);
+    }
+
+    fn arpeggiate(&self, batch: Vec<MidiData>, rate: Duration) {
+        // pressure is dropped, as the notes it refers to only sound intermittently
+        let mut held = self.chords.held.lock().unwrap();
+        for data in batch {
+            if data.is_note_on() {
+                held.push(data);
+            } else if data.is_note_off() {
+                held.retain(|on| (on.channel, on.note) != (data.channel, data.note));
+            }
+        }
+        if held.is_empty() || self.chords.arpeggiating.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        drop(held);
+
+        let chords = self.chords.clone();
+        let pattern = self.pattern;
+        go!(move || {
+            let mut step = 0;
+            loop {
+                let data = {
+                    let held = chords.held.lock().unwrap();
+                    if held.is_empty() {
+                        chords.arpeggiating.store(false, Ordering::Relaxed);
+                        return;
+                    }
+                    let sequence = order(pattern, &held);
+                    sequence[step % sequence.len()]
+                };
+                step += 1;
+                chords.next.call(data);
+                sleep(rate);
+                chords.next.call(MidiData { instruction: NOTE_OFF, velocity: 0, ..data });
+            }
+        });
+    }
+
+    fn split(&self, batch: Vec<MidiData>, channels: &[u8]) {
+        let mut sounding = self.chords.sounding.lock().unwrap();
+        let mut messages = Vec::new();
+        for mut data in batch {
+            let key = (data.channel, data.note);
+            if data.is_note_on() {
+                let channel = channels[self.chords.turn.fetch_add(1, Ordering::Relaxed) % channels.len()];
+                sounding.insert(key, (channel, Instant::now()));
+                data.channel = channel;
+            } else if data.is_note_off() {
+                if let Some((channel, _due)) = sounding.remove(&key) {
+                    data.channel = channel;
+                }
+            } else if let Some((channel, _due)) = sounding.get(&key) {
+                data.channel = *channel;
+            }
+            messages.push((data, 0f32));
+        }
+        drop(sounding);
+        // each note is sent on its own coroutine, such that every channel receives its note at once
+        dispatch(&self.name, &self.chords.next, Instant::now(), Duration::ZERO, messages);
+    }
+}
+
+impl Node for Arpeggiator {
+    fn call(&self, data: MidiData) {
+        if !data.is_note() {
+            self.chords.next.call(data);
+            return;
+        }
+
+        // the first note of a window collects every note received until it closes
+        {
+            let mut pending = self.chords.pending.lock().unwrap();
+            pending.push(data);
+            if pending.len() > 1 {
+                return;
+            }
+        }
+        sleep(self.window);
+        let batch = std::mem::take(&mut *self.chords.pending.lock().unwrap());
+
+        match &self.style {
+            ChordStyle::Strum { spread } => self.strum(batch, *spread),
+            ChordStyle::Arpeggiate { rate } => self.arpeggiate(batch, *rate),
+            ChordStyle::Split { channels } => self.split(batch, channels)
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.chords.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::NOTE_ON;
+    use crate::node::testing::Recorder;
+    use super::*;
+
+    fn message(instruction: u8, channel: u8, note: u8) -> MidiData {
+        MidiData { instruction, channel, note, velocity: if instruction == NOTE_ON { 100 } else { 0 } }
+    }
+
+    fn sorted(mut messages: Vec<MidiData>) -> Vec<MidiData> {
+        messages.sort_by_key(|data| (data.instruction, data.note));
+        messages
+    }
+
+    #[test]
+    fn up_down_patterns_repeat_neither_end() {
+        let notes = [message(NOTE_ON, 0, 64), message(NOTE_ON, 0, 60), message(NOTE_ON, 0, 67)];
+        let ordered: Vec<u8> = order(ChordPattern::UpDown, &notes).iter().map(|data| data.note).collect();
+        assert_eq!(ordered, [60, 64, 67, 64]);
+        let ordered: Vec<u8> = order(ChordPattern::Played, &notes).iter().map(|data| data.note).collect();
+        assert_eq!(ordered, [64, 60, 67]);
+    }
+
+    #[test]
+    fn strummed_note_offs_wait_for_their_note_on() {
+        let spread = Duration::from_millis(100);
+        let arpeggiator = Arpeggiator::new("Arpeggiator", Duration::ZERO, ChordPattern::Up, ChordStyle::Strum { spread });
+        let recorder = Recorder::after(&arpeggiator);
+        arpeggiator.strum(vec![message(NOTE_ON, 0, 67), message(NOTE_ON, 0, 60), message(NOTE_OFF, 0, 67)], spread);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(recorder.take(), [message(NOTE_ON, 0, 60)]);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(sorted(recorder.take()), [message(NOTE_OFF, 0, 67), message(NOTE_ON, 0, 67)]);
+    }
+
+    #[test]
+    fn split_note_offs_follow_their_note_on() {
+        let channels = vec![1, 2];
+        let style = ChordStyle::Split { channels: channels.clone() };
+        let arpeggiator = Arpeggiator::new("Arpeggiator", Duration::ZERO, ChordPattern::Up, style);
+        let recorder = Recorder::after(&arpeggiator);
+        arpeggiator.split(vec![message(NOTE_ON, 0, 60), message(NOTE_ON, 0, 64)], &channels);
+        arpeggiator.split(vec![message(NOTE_OFF, 0, 64)], &channels);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            sorted(recorder.take()),
+            [message(NOTE_OFF, 2, 64), message(NOTE_ON, 1, 60), message(NOTE_ON, 2, 64)]
+        );
+    }
+}
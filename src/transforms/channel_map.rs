@@ -0,0 +1,51 @@
+use std::sync::Weak;
+use may::sync::RwLock;
+use serde::Deserialize;
+use crate::data::{MidiData, SYSTEM};
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelMapOptions {
+    // forces every message onto a single channel
+    pub(crate) channel: Option<u8>,
+    // otherwise an `input: output` map, leaving unmapped channels as is
+    #[serde(default, with = "tuple_vec_map")]
+    pub(crate) channels: Vec<(u8, u8)>
+}
+
+// remaps the channel of every channel message, either onto a single channel, or by an input -> output mapping. System
+// messages, whose low nibble identifies the message rather than a channel, are left as is
+pub(crate) struct ChannelMap {
+    channels: [u8; 16],
+    next: OptNode
+}
+
+impl ChannelMap {
+    pub(crate) fn new(channel: Option<u8>, mapping: &[(u8, u8)]) -> Self {
+        let mut channels: [u8; 16] = std::array::from_fn(|channel| channel as u8);
+        for &(input, output) in mapping {
+            channels[input as usize] = output;
+        }
+        if let Some(channel) = channel {
+            channels = [channel; 16];
+        }
+        ChannelMap {
+            channels,
+            next: RwLock::new(None)
+        }
+    }
+}
+
+impl Node for ChannelMap {
+    fn call(&self, mut data: MidiData) {
+        if data.instruction != SYSTEM {
+            data.channel = self.channels[(data.channel & 0b1111) as usize];
+        }
+        self.next.call(data);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::sync::{RwLock as StdRwLock, Weak};
+use std::time::{Duration, Instant};
+use log::{trace, warn};
+use may::coroutine::sleep;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::config::Arrivals;
+use crate::data::{MidiData, CONTINUE, SONG_POSITION, START, STOP, TIMING_CLOCK};
+use crate::node::{Node, OptNode};
+
+pub(crate) const TICKS_PER_BEAT: f64 = 24.0;
+// song positions are given in sixteenth notes
+const TICKS_PER_SONG_POSITION: u64 = 6;
+// ticks further apart than this are taken to be the clock resuming, rather than a change in tempo
+const MAX_TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClockOptions {
+    // the Outputs clock and transport are sent to, each after the latency of the notes reaching it
+    pub(crate) outputs: Vec<ClockOutputConfig>,
+    // whether clock and transport are also passed on to `next`
+    #[serde(default)]
+    pub(crate) forward: bool
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ClockOutputConfig {
+    pub(crate) to: String,
+    // in seconds, added to the latency, such as to account for how quickly an instrument follows the clock
+    #[serde(default)]
+    pub(crate) offset: f32
+}
+
+// the tempo of whichever Clock last received a tick, in beats per minute
+static TEMPO: StdRwLock<Option<f64>> = StdRwLock::new(None);
+
+pub(crate) fn tempo() -> Option<f64> {
+    *TEMPO.read().unwrap()
+}
+
+// follows the length of a beat, in seconds, from the interval between clock ticks
+pub(crate) struct TickTimer {
+    beat: Option<f64>,
+    last: Option<Instant>
+}
+
+impl TickTimer {
+    pub(crate) fn new(beat: Option<f64>) -> Self {
+        TickTimer { beat, last: None }
+    }
+
+    pub(crate) fn tick(&mut self, now: Instant) {
+        if let Some(interval) = self.last.map(|last| now - last).filter(|interval| *interval < MAX_TICK_INTERVAL) {
+            let beat = interval.as_secs_f64() * TICKS_PER_BEAT;
+            // smoothed, as ticks are subject to jitter
+            self.beat = Some(self.beat.map_or(beat, |prev| prev * 0.9 + beat * 0.1));
+        }
+        self.last = Some(now);
+    }
+
+    pub(crate) fn beat(&self) -> Option<f64> {
+        self.beat
+    }
+}
+
+struct Transport {
+    timer: TickTimer,
+    playing: bool,
+    // in ticks from the start of the song
+    position: u64
+}
+
+impl Transport {
+    fn receive(&mut self, data: &MidiData, now: Instant) {
+        match data.channel {
+            TIMING_CLOCK => {
+                self.timer.tick(now);
+                if self.playing {
+                    self.position += 1;
+                }
+            }
+            START => {
+                self.playing = true;
+                self.position = 0;
+            }
+            CONTINUE => self.playing = true,
+            STOP => self.playing = false,
+            SONG_POSITION => {
+                let position = ((data.velocity as u64 & 0x7F) << 7) | (data.note as u64 & 0x7F);
+                self.position = position * TICKS_PER_SONG_POSITION;
+            }
+            _ => {}
+        }
+    }
+}
+
+// an Output receiving clock and transport, in order and after the latency of the path its notes take
+struct ClockOutput {
+    to: String,
+    // in seconds, added to the latency
+    offset: f32,
+    // resolved once the whole graph is built
+    delay: RwLock<Duration>,
+    pending: Mutex<VecDeque<(Instant, MidiData)>>,
+    node: OptNode
+}
+
+impl ClockOutput {
+    // sends everything due by now, which includes messages whose own coroutines have yet to wake
+    fn drain(&self) {
+        let mut due = Vec::new();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let now = Instant::now();
+            while pending.front().is_some_and(|(at, _data)| *at <= now) {
+                due.extend(pending.pop_front().map(|(_at, data)| data));
+            }
+        }
+        for data in due {
+            self.node.call(data);
+        }
+    }
+}
+
+// forwards clock and transport straight to each Output, delayed by the latency of the notes reaching it such that
+// anything following the clock (such as a robot's own sequences or lights) lines up with the notes it plays. Every
+// other message is passed on to `next`
+pub(crate) struct Clock {
+    name: String,
+    outputs: Vec<ClockOutput>,
+    // whether clock and transport are also passed on to `next`
+    forward: bool,
+    transport: Mutex<Transport>,
+    next: OptNode
+}
+
+impl Clock {
+    pub(crate) fn new(name: &str, outputs: Vec<(String, f32)>, forward: bool) -> Self {
+        Clock {
+            name: String::from(name),
+            outputs: outputs.into_iter()
+                .map(|(to, offset)| ClockOutput {
+                    to,
+                    offset,
+                    delay: RwLock::new(Duration::ZERO),
+                    pending: Mutex::new(VecDeque::new()),
+                    node: RwLock::new(None)
+                })
+                .collect(),
+            forward,
+            transport: Mutex::new(Transport { timer: TickTimer::new(None), playing: false, position: 0 }),
+            next: RwLock::new(None)
+        }
+    }
+}
+
+impl Node for Clock {
+    fn call(&self, data: MidiData) {
+        if !data.is_transport() {
+            self.next.call(data);
+            return;
+        }
+        let start = Instant::now();
+        {
+            let mut transport = self.transport.lock().unwrap();
+            transport.receive(&data, start);
+            if data.is_system(TIMING_CLOCK) {
+                *TEMPO.write().unwrap() = transport.timer.beat().map(|beat| 60.0 / beat);
+            }
+        }
+        if self.forward {
+            self.next.call(data);
+        }
+
+        // in order of when they are due
+        let mut outputs: Vec<(&ClockOutput, Instant)> = self.outputs.iter()
+            .filter_map(|output| {
+                let delay = *output.delay.read().unwrap();
+                let due = start.checked_add(delay);
+                if due.is_none() {
+                    warn!(target: &self.name, "Dropping {:?} for {}, as its delay of {:?} is too long", data, output.to, delay);
+                }
+                due.map(|due| (output, due))
+            })
+            .collect();
+        outputs.sort_by_key(|(_output, due)| *due);
+        for (output, due) in outputs.iter() {
+            output.pending.lock().unwrap().push_back((*due, data));
+        }
+        for (output, due) in outputs {
+            let now = Instant::now();
+            if due > now {
+                sleep(due - now);
+            }
+            trace!(target: &self.name, "Sending {:?} to {}", data, output.to);
+            output.drain();
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn targets(&self) -> Vec<String> {
+        self.outputs.iter().map(|output| output.to.clone()).collect()
+    }
+
+    fn bind_target(&self, target: &str, node: Weak<dyn Node>) {
+        for output in self.outputs.iter().filter(|output| output.to == target) {
+            output.node.bind(node.clone());
+        }
+    }
+
+    fn delay_to(&self, target: &str) -> Duration {
+        self.outputs.iter()
+            .find(|output| output.to == target)
+            .map(|output| *output.delay.read().unwrap())
+            .unwrap_or_default()
+    }
+
+    // the delay to each Output follows the latency of the notes reaching it, floored at zero
+    fn resolve(&self, _name: &str, arrivals: &Arrivals) {
+        for output in self.outputs.iter() {
+            let delay = arrivals.delay(&output.to).as_secs_f64() + output.offset as f64;
+            *output.delay.write().unwrap() = Duration::try_from_secs_f64(delay.max(0f64)).unwrap_or(Duration::MAX);
+        }
+    }
+
+    fn state(&self) -> Value {
+        let delays: serde_json::Map<String, Value> = self.outputs.iter()
+            .map(|output| (output.to.clone(), json!(output.delay.read().unwrap().as_secs_f64())))
+            .collect();
+        let transport = self.transport.lock().unwrap();
+        json!({
+            "tempo": transport.timer.beat().map(|beat| 60.0 / beat),
+            "playing": transport.playing,
+            // in beats
+            "position": transport.position as f64 / TICKS_PER_BEAT,
+            "delays": delays
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use serde_json::json;
+    use crate::config::Graph;
+    use crate::data::{NOTE_ON, SYSTEM};
+    use crate::node::testing::Recorder;
+    use super::*;
+
+    fn message(instruction: u8, channel: u8) -> MidiData {
+        MidiData { instruction, channel, note: 0, velocity: 0 }
+    }
+
+    #[test]
+    fn transport_goes_to_outputs_and_notes_to_next() {
+        let clock = Clock::new("Clock", vec![(String::from("Out"), 0.0)], false);
+        let next = Recorder::after(&clock);
+        let output = Recorder::new();
+        let target: Arc<dyn Node> = output.clone();
+        clock.bind_target("Out", Arc::downgrade(&target));
+
+        clock.call(message(NOTE_ON, 0));
+        clock.call(message(SYSTEM, START));
+        assert_eq!(next.take(), [message(NOTE_ON, 0)]);
+        assert_eq!(output.take(), [message(SYSTEM, START)]);
+        assert_eq!(clock.state()["playing"], json!(true));
+    }
+
+    #[test]
+    fn output_delays_follow_paths_declared_after_the_clock() {
+        let graph = Graph::from_yaml("
+            - name: Clock
+              type: Clock
+              outputs:
+                - to: Out
+                  offset: -0.05
+              next: Delay
+            - name: Delay
+              type: DelayNode
+              duration: 0.2
+              next: Out
+            - name: Out
+              type: DebugNode
+        ").unwrap();
+        let delay = Duration::from_secs_f64(Duration::from_secs_f32(0.2).as_secs_f64() - 0.05f32 as f64);
+        assert_eq!(graph.get("Clock").unwrap().state()["delays"], json!({ "Out": delay.as_secs_f64() }));
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use std::sync::Weak;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use crate::config::MessageMatch;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterOptions {
+    #[serde(rename = "match")]
+    pub(crate) matcher: MessageMatch,
+    // drops matching messages instead
+    #[serde(default)]
+    pub(crate) invert: bool
+}
+
+pub(crate) struct Filter {
+    matcher: MessageMatch,
+    invert: bool,
+    // the notes whose note-on was passed, such that only their note-offs are passed in turn
+    active: Mutex<HashSet<(u8, u8)>>,
+    next: OptNode
+}
+
+impl Filter {
+    pub(crate) fn new(matcher: MessageMatch, invert: bool) -> Self {
+        Filter {
+            matcher,
+            invert,
+            active: Mutex::new(HashSet::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    fn passes(&self, data: &MidiData) -> bool {
+        let key = (data.channel, data.note);
+        if data.is_note_off() {
+            return self.active.lock().unwrap().remove(&key);
+        }
+        let passes = self.matcher.matches(data) != self.invert;
+        if passes && data.is_note_on() {
+            self.active.lock().unwrap().insert(key);
+        }
+        passes
+    }
+}
+
+impl Node for Filter {
+    fn call(&self, data: MidiData) {
+        if self.passes(&data) {
+            self.next.call(data);
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}
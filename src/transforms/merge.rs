@@ -0,0 +1,184 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use may::coroutine::sleep;
+use may::sync::{Mutex, RwLock};
+use serde_json::{json, Value};
+use crate::config::Arrivals;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+struct Pending {
+    due: Instant,
+    // breaks ties between messages due at once, in order of arrival
+    seq: u64,
+    data: MidiData
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+// messages from every branch, sent on in order of when they are due
+struct Queue {
+    pending: Mutex<BinaryHeap<Reverse<Pending>>>,
+    seq: AtomicU64,
+    next: OptNode
+}
+
+impl Queue {
+    fn send(&self, data: MidiData, compensation: Duration) {
+        let due = Instant::now() + compensation;
+        let seq = self.seq.fetch_add(1, AtomicOrdering::Relaxed);
+        self.pending.lock().unwrap().push(Reverse(Pending { due, seq, data }));
+        sleep(compensation);
+
+        // anything due by now is sent, including messages from other branches whose coroutines have yet to wake
+        let mut due = Vec::new();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let now = Instant::now();
+            while pending.peek().is_some_and(|Reverse(first)| first.due <= now) {
+                due.extend(pending.pop().map(|Reverse(first)| first.data));
+            }
+        }
+        for data in due {
+            self.next.call(data);
+        }
+    }
+}
+
+// the receiving end of a single upstream branch
+struct Inlet {
+    compensation: RwLock<Duration>,
+    queue: Arc<Queue>
+}
+
+impl Node for Inlet {
+    fn call(&self, data: MidiData) {
+        let compensation = *self.compensation.read().unwrap();
+        self.queue.send(data, compensation);
+    }
+
+    fn bind(&self, _node: Weak<dyn Node>) {}
+
+    fn delay(&self) -> Duration {
+        *self.compensation.read().unwrap()
+    }
+}
+
+// joins several upstream branches, delaying each so that all of them arrive after the slowest one's cumulative delay.
+// Compensation is resolved once the whole graph is built, and again whenever delays change at runtime
+pub(crate) struct Merge {
+    inlets: Mutex<HashMap<String, Arc<Inlet>>>,
+    // applied to messages sent to the node other than through an inlet, treating them as undelayed
+    compensation: RwLock<Duration>,
+    queue: Arc<Queue>
+}
+
+impl Merge {
+    pub(crate) fn new() -> Self {
+        Merge {
+            inlets: Mutex::new(HashMap::new()),
+            compensation: RwLock::new(Duration::ZERO),
+            queue: Arc::new(Queue {
+                pending: Mutex::new(BinaryHeap::new()),
+                seq: AtomicU64::new(0),
+                next: RwLock::new(None)
+            })
+        }
+    }
+}
+
+impl Node for Merge {
+    fn call(&self, data: MidiData) {
+        let compensation = *self.compensation.read().unwrap();
+        self.queue.send(data, compensation);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.queue.next.bind(node);
+    }
+
+    fn inlet(&self, from: &str) -> Option<Arc<dyn Node>> {
+        let inlet: Arc<dyn Node> = self.inlets.lock().unwrap()
+            .entry(String::from(from))
+            .or_insert_with(|| Arc::new(Inlet { compensation: RwLock::new(Duration::ZERO), queue: self.queue.clone() }))
+            .clone();
+        Some(inlet)
+    }
+
+    // branches within a cycle, or no longer bound, are treated as undelayed
+    fn resolve(&self, name: &str, arrivals: &Arrivals) {
+        let arrivals = arrivals.get(name);
+        let slowest = arrivals.iter().map(|(_from, arrival)| *arrival).max().unwrap_or_default();
+        *self.compensation.write().unwrap() = slowest;
+        for (from, inlet) in self.inlets.lock().unwrap().iter() {
+            let arrival = arrivals.iter().find(|(prev, _arrival)| prev == from).map(|(_prev, arrival)| *arrival);
+            *inlet.compensation.write().unwrap() = slowest - arrival.unwrap_or_default();
+        }
+    }
+
+    fn state(&self) -> Value {
+        let compensation: serde_json::Map<String, Value> = self.inlets.lock().unwrap().iter()
+            .map(|(from, inlet)| (from.clone(), json!(inlet.compensation.read().unwrap().as_secs_f64())))
+            .collect();
+        json!({ "compensation": compensation })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::config::Graph;
+    use crate::control::Mode;
+    use super::*;
+
+    const BRANCHES: &str = "
+        - name: Slow
+          type: DelayNode
+          duration: 0.2
+          next: Merge
+        - name: Fast
+          type: DelayNode
+          duration: 0.05
+          next: Merge
+        - name: Merge
+          type: Merge
+    ";
+
+    #[test]
+    fn faster_branches_are_delayed_to_match_the_slowest() {
+        let graph = Graph::from_yaml(BRANCHES).unwrap();
+        let merge = graph.get("Merge").unwrap();
+        let fast = (Duration::from_secs_f32(0.2) - Duration::from_secs_f32(0.05)).as_secs_f64();
+        assert_eq!(merge.state(), json!({ "compensation": { "Slow": 0.0, "Fast": fast } }));
+    }
+
+    #[test]
+    fn bypassed_branches_are_compensated_for() {
+        let graph = Graph::from_yaml(BRANCHES).unwrap();
+        graph.get("Slow").unwrap().set_mode(Mode::Bypassed);
+        graph.resolve();
+        let slow = Duration::from_secs_f32(0.05).as_secs_f64();
+        assert_eq!(graph.get("Merge").unwrap().state(), json!({ "compensation": { "Slow": slow, "Fast": 0.0 } }));
+    }
+}
@@ -0,0 +1,21 @@
+mod transpose;
+mod arpeggiator;
+mod velocity_map;
+mod channel_map;
+mod router;
+mod filter;
+mod merge;
+mod note_guard;
+mod quantise;
+pub(crate) mod clock;
+
+pub(crate) use transpose::{Transpose, TransposeOptions};
+pub(crate) use arpeggiator::{Arpeggiator, ArpeggiatorOptions, ChordMode, ChordStyle};
+pub(crate) use velocity_map::{VelocityMap, VelocityMapOptions};
+pub(crate) use channel_map::{ChannelMap, ChannelMapOptions};
+pub(crate) use router::{Router, RouterOptions};
+pub(crate) use filter::{Filter, FilterOptions};
+pub(crate) use merge::Merge;
+pub(crate) use note_guard::{NoteGuard, NoteGuardOptions};
+pub(crate) use quantise::{Grid, Humanise, Quantise, QuantiseOptions};
+pub(crate) use clock::{Clock, ClockOptions};
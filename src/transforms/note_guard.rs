@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use log::{debug, warn};
+use may::coroutine::sleep;
+use may::go;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::data::{MidiData, ALL_NOTES_OFF, ALL_SOUND_OFF, CONTROL_CHANGE, NOTE_OFF};
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NoteGuardOptions {
+    // in seconds, after which sounding notes are released
+    #[serde(default = "default_max_length")]
+    pub(crate) max_length: f32
+}
+
+fn default_max_length() -> f32 {
+    10.0
+}
+
+struct Notes {
+    // the generation of each sounding note by (channel, note), so that timeouts of released notes are abandoned
+    active: Mutex<HashMap<(u8, u8), u64>>,
+    generation: AtomicU64,
+    next: OptNode
+}
+
+impl Notes {
+    fn release(&self, channel: u8, note: u8) {
+        self.next.call(MidiData { instruction: NOTE_OFF, channel, note, velocity: 0 });
+    }
+}
+
+// ensures every note-on downstream is matched by exactly one note-off, releasing notes held for too long
+pub(crate) struct NoteGuard {
+    name: String,
+    max_length: RwLock<Duration>,
+    notes: Arc<Notes>
+}
+
+impl NoteGuard {
+    pub(crate) fn new(name: &str, max_length: Duration) -> Self {
+        NoteGuard {
+            name: String::from(name),
+            max_length: RwLock::new(max_length),
+            notes: Arc::new(Notes {
+                active: Mutex::new(HashMap::new()),
+                generation: AtomicU64::new(0),
+                next: RwLock::new(None)
+            })
+        }
+    }
+
+    fn note_on(&self, data: MidiData) {
+        let key = (data.channel, data.note);
+        let generation = self.notes.generation.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut active = self.notes.active.lock().unwrap();
+            if active.contains_key(&key) {
+                debug!(target: &self.name, "Dropping repeated {:?}", data);
+                return;
+            }
+            active.insert(key, generation);
+        }
+        self.notes.next.call(data);
+
+        let notes = self.notes.clone();
+        let name = self.name.clone();
+        let max_length = *self.max_length.read().unwrap();
+        go!(move || {
+            sleep(max_length);
+            let expired = {
+                let mut active = notes.active.lock().unwrap();
+                let expired = active.get(&key) == Some(&generation);
+                if expired {
+                    active.remove(&key);
+                }
+                expired
+            };
+            if expired {
+                warn!(target: &name, "Releasing note {} on channel {} held for longer than {:?}", key.1, key.0, max_length);
+                notes.release(key.0, key.1);
+            }
+        });
+    }
+
+    fn note_off(&self, data: MidiData) {
+        if self.notes.active.lock().unwrap().remove(&(data.channel, data.note)).is_some() {
+            self.notes.next.call(data);
+        } else {
+            debug!(target: &self.name, "Dropping {:?} for a note that isn't sounding", data);
+        }
+    }
+
+    fn release_channel(&self, channel: u8) {
+        let released: Vec<u8> = {
+            let mut active = self.notes.active.lock().unwrap();
+            let released = active.keys().filter(|(ch, _note)| *ch == channel).map(|(_ch, note)| *note).collect();
+            active.retain(|(ch, _note), _generation| *ch != channel);
+            released
+        };
+        for note in released {
+            self.notes.release(channel, note);
+        }
+    }
+}
+
+impl Node for NoteGuard {
+    fn call(&self, data: MidiData) {
+        if data.is_note_on() {
+            self.note_on(data);
+        } else if data.is_note_off() {
+            self.note_off(data);
+        } else {
+            if data.instruction == CONTROL_CHANGE && matches!(data.note, ALL_SOUND_OFF | ALL_NOTES_OFF) {
+                self.release_channel(data.channel);
+            }
+            self.notes.next.call(data);
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.notes.next.bind(node);
+    }
+
+    fn state(&self) -> Value {
+        let mut active: Vec<(u8, u8)> = self.notes.active.lock().unwrap().keys().copied().collect();
+        active.sort_unstable();
+        json!({
+            "max_length": self.max_length.read().unwrap().as_secs_f64(),
+            "active": active
+        })
+    }
+
+    fn set(&self, param: &str, value: &Value) -> Result<(), String> {
+        match param {
+            "max_length" => {
+                let max_length = value.as_f64()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .filter(|max_length| !max_length.is_zero())
+                    .ok_or(format!("Expected a positive duration in seconds, got {}", value))?;
+                *self.max_length.write().unwrap() = max_length;
+                Ok(())
+            }
+            param => Err(format!("Unknown parameter: {}", param))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::NOTE_ON;
+    use crate::node::testing::Recorder;
+    use super::*;
+
+    fn message(instruction: u8, channel: u8, note: u8, velocity: u8) -> MidiData {
+        MidiData { instruction, channel, note, velocity }
+    }
+
+    #[test]
+    fn notes_are_passed_on_and_off_once() {
+        let guard = NoteGuard::new("NoteGuard", Duration::from_secs(10));
+        let recorder = Recorder::after(&guard);
+        guard.call(message(NOTE_OFF, 0, 40, 0));
+        guard.call(message(NOTE_ON, 0, 40, 100));
+        guard.call(message(NOTE_ON, 0, 40, 90));
+        guard.call(message(NOTE_OFF, 0, 40, 0));
+        guard.call(message(NOTE_OFF, 0, 40, 0));
+        assert_eq!(recorder.take(), [message(NOTE_ON, 0, 40, 100), message(NOTE_OFF, 0, 40, 0)]);
+    }
+
+    #[test]
+    fn all_notes_off_releases_the_channel() {
+        let guard = NoteGuard::new("NoteGuard", Duration::from_secs(10));
+        let recorder = Recorder::after(&guard);
+        guard.call(message(NOTE_ON, 0, 40, 100));
+        guard.call(message(NOTE_ON, 1, 41, 100));
+        recorder.take();
+        guard.call(message(CONTROL_CHANGE, 0, ALL_NOTES_OFF, 0));
+        assert_eq!(recorder.take(), [message(NOTE_OFF, 0, 40, 0), message(CONTROL_CHANGE, 0, ALL_NOTES_OFF, 0)]);
+        assert_eq!(guard.state()["active"], json!([[1, 41]]));
+    }
+
+    #[test]
+    fn held_notes_are_released() {
+        let guard = NoteGuard::new("NoteGuard", Duration::from_millis(20));
+        let recorder = Recorder::after(&guard);
+        guard.call(message(NOTE_ON, 0, 40, 100));
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(recorder.take(), [message(NOTE_ON, 0, 40, 100), message(NOTE_OFF, 0, 40, 0)]);
+        // the note-off arriving late is dropped, as the note was already released
+        guard.call(message(NOTE_OFF, 0, 40, 0));
+        assert_eq!(recorder.take(), []);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::sync::Weak;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::data::{MidiData, START, TIMING_CLOCK};
+use crate::node::{dispatch, Node, OptNode};
+use crate::transforms::clock::{self, TickTimer, TICKS_PER_BEAT};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuantiseOptions {
+    // in beats per minute, until any MIDI clock is received
+    pub(crate) tempo: Option<f32>,
+    // in beats per minute, the slowest tempo notes are kept from being sent early at. Defaults to `tempo`
+    pub(crate) min_tempo: Option<f32>,
+    // in beats, the spacing of the grid notes are moved onto
+    pub(crate) grid: Option<f32>,
+    // the fraction of a step by which every other grid step is delayed
+    #[serde(default)]
+    pub(crate) swing: f32,
+    pub(crate) humanise: Option<HumaniseOptions>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HumaniseOptions {
+    // in seconds, the furthest notes are moved either way
+    #[serde(default)]
+    pub(crate) timing: f32,
+    // the furthest note-on velocities are changed either way
+    #[serde(default)]
+    pub(crate) velocity: u8,
+    pub(crate) seed: Option<u64>
+}
+
+// SplitMix64, so that humanised performances may be reproduced from their seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: Option<u64>) -> Self {
+        Rng(seed.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or_default()
+        }))
+    }
+
+    // uniformly distributed within [-1, 1)
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+pub(crate) struct Grid {
+    // in beats
+    pub(crate) step: f64,
+    // the fraction of a step by which every other step is delayed
+    pub(crate) swing: f64
+}
+
+impl Grid {
+    // the grid point nearest to the given position, in beats
+    fn nearest(&self, position: f64) -> f64 {
+        let pair = (position / (2.0 * self.step)).floor() * 2.0 * self.step;
+        [pair, pair + self.step * (1.0 + self.swing), pair + 2.0 * self.step].into_iter()
+            .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()))
+            .unwrap_or(position)
+    }
+}
+
+pub(crate) struct Humanise {
+    // the furthest notes are moved either way
+    pub(crate) timing: Duration,
+    pub(crate) velocity: u8,
+    pub(crate) seed: Option<u64>
+}
+
+// the musical position of the performance, either at a fixed tempo or following MIDI clock
+struct Timeline {
+    // the time at which `beats` were reached
+    anchor: Instant,
+    beats: f64,
+    timer: TickTimer,
+    ticks: i64
+}
+
+impl Timeline {
+    fn tick(&mut self, now: Instant) {
+        self.timer.tick(now);
+        self.ticks += 1;
+        self.anchor = now;
+        self.beats = self.ticks as f64 / TICKS_PER_BEAT;
+    }
+
+    // in seconds, if known, falling back on the tempo any Clock node is following
+    fn beat(&self) -> Option<f64> {
+        self.timer.beat().or_else(|| clock::tempo().map(|tempo| 60.0 / tempo))
+    }
+
+    fn position(&self, now: Instant, beat: f64) -> f64 {
+        self.beats + (now - self.anchor).as_secs_f64() / beat
+    }
+}
+
+// moves notes onto a grid and/or varies their timing and velocity, delaying note-offs alongside their note-on
+pub(crate) struct Quantise {
+    name: String,
+    grid: Option<Grid>,
+    humanise: Option<Humanise>,
+    timeline: Mutex<Timeline>,
+    rng: Mutex<Rng>,
+    // by which every message besides notes is delayed, and around which notes are moved. Fixed, so that successors
+    // may compensate for it
+    latency: Duration,
+    // how much each sounding note was delayed by
+    shifts: Mutex<HashMap<(u8, u8), Duration>>,
+    next: OptNode
+}
+
+impl Quantise {
+    // notes are moved by less than a grid step either way, so delaying every message by a step at the slowest tempo
+    // keeps them from being early
+    pub(crate) fn new(
+        name: &str,
+        tempo: Option<f64>,
+        min_tempo: Option<f64>,
+        grid: Option<Grid>,
+        humanise: Option<Humanise>
+    ) -> Self {
+        let step = grid.as_ref().zip(min_tempo)
+            .map(|(grid, min_tempo)| grid.step * 60.0 / min_tempo)
+            .unwrap_or_default();
+        let timing = humanise.as_ref().map(|humanise| humanise.timing).unwrap_or_default();
+        Quantise {
+            name: String::from(name),
+            latency: Duration::from_secs_f64(step) + timing,
+            grid,
+            rng: Mutex::new(Rng::new(humanise.as_ref().and_then(|humanise| humanise.seed))),
+            humanise,
+            timeline: Mutex::new(Timeline {
+                anchor: Instant::now(),
+                beats: 0.0,
+                timer: TickTimer::new(tempo.map(|tempo| 60.0 / tempo)),
+                ticks: 0
+            }),
+            shifts: Mutex::new(HashMap::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    // below the minimum tempo, notes which would be early are sent with no delay instead
+    fn note_on(&self, data: &mut MidiData, now: Instant) -> Duration {
+        let mut shift = self.latency.as_secs_f64();
+        if let Some(grid) = &self.grid {
+            let timeline = self.timeline.lock().unwrap();
+            if let Some(beat) = timeline.beat() {
+                let position = timeline.position(now, beat);
+                shift += (grid.nearest(position) - position) * beat;
+            }
+        }
+        if let Some(humanise) = &self.humanise {
+            let mut rng = self.rng.lock().unwrap();
+            shift += humanise.timing.as_secs_f64() * rng.next();
+            let velocity = data.velocity as f64 + (humanise.velocity as f64 * rng.next()).round();
+            data.velocity = velocity.clamp(1.0, 127.0) as u8;
+        }
+        Duration::from_secs_f64(shift.max(0.0))
+    }
+}
+
+impl Node for Quantise {
+    fn call(&self, mut data: MidiData) {
+        let now = Instant::now();
+        if data.is_system(TIMING_CLOCK) {
+            self.timeline.lock().unwrap().tick(now);
+            self.next.call(data);
+            return;
+        }
+        if data.is_system(START) {
+            // the tick following a start marks the first beat
+            self.timeline.lock().unwrap().ticks = -1;
+            self.next.call(data);
+            return;
+        }
+
+        let key = (data.channel, data.note);
+        let shift = if data.is_note_on() {
+            let shift = self.note_on(&mut data, now);
+            self.shifts.lock().unwrap().insert(key, shift);
+            shift
+        } else if data.is_note_off() {
+            self.shifts.lock().unwrap().remove(&key).unwrap_or(self.latency)
+        } else if data.is_note() {
+            self.shifts.lock().unwrap().get(&key).copied().unwrap_or(self.latency)
+        } else {
+            self.latency
+        };
+        dispatch(&self.name, &self.next, now, shift, vec![(data, 0.0)]);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn delay(&self) -> Duration {
+        self.latency
+    }
+
+    fn state(&self) -> Value {
+        let beat = self.timeline.lock().unwrap().beat();
+        json!({ "tempo": beat.map(|beat| 60.0 / beat) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{NOTE_ON, SYSTEM};
+    use super::*;
+
+    #[test]
+    fn straight_grids_round_to_the_nearest_step() {
+        let grid = Grid { step: 0.25, swing: 0.0 };
+        assert_eq!(grid.nearest(0.1), 0.0);
+        assert_eq!(grid.nearest(0.2), 0.25);
+        assert_eq!(grid.nearest(0.6), 0.5);
+        assert_eq!(grid.nearest(0.9), 1.0);
+    }
+
+    #[test]
+    fn swing_delays_every_other_step() {
+        let grid = Grid { step: 0.5, swing: 0.5 };
+        // the offbeat moves from 0.5 to 0.75
+        assert_eq!(grid.nearest(0.6), 0.75);
+        assert_eq!(grid.nearest(0.8), 0.75);
+        assert_eq!(grid.nearest(0.3), 0.0);
+        // the gap after it shrinks to a quarter beat
+        assert_eq!(grid.nearest(0.9), 1.0);
+        assert_eq!(grid.nearest(1.7), 1.75);
+    }
+
+    #[test]
+    fn notes_are_moved_by_less_than_a_step() {
+        for grid in [Grid { step: 0.25, swing: 0.0 }, Grid { step: 0.5, swing: 0.5 }, Grid { step: 1.0 / 3.0, swing: 0.9 }] {
+            for index in 0..1000 {
+                let position = index as f64 * 0.0037;
+                let moved = (grid.nearest(position) - position).abs();
+                // at most half of the longer, swung gap between grid points
+                assert!(moved <= grid.step * (1.0 + grid.swing) / 2.0 + 1e-9, "{} moved by {}", position, moved);
+            }
+        }
+    }
+
+    #[test]
+    fn latency_is_one_step_at_the_minimum_tempo() {
+        let humanise = Humanise { timing: Duration::from_millis(10), velocity: 0, seed: Some(1) };
+        let grid = Grid { step: 0.25, swing: 0.5 };
+        let quantise = Quantise::new("Quantise", Some(120.0), Some(60.0), Some(grid), Some(humanise));
+        assert_eq!(quantise.delay(), Duration::from_millis(260));
+        // following a faster clock leaves it unchanged
+        let start = Instant::now();
+        for tick in 0..48 {
+            quantise.call(MidiData { instruction: SYSTEM, channel: TIMING_CLOCK, note: 0, velocity: 0 });
+            std::thread::sleep((start + Duration::from_millis(10) * tick).saturating_duration_since(Instant::now()));
+        }
+        assert_eq!(quantise.delay(), Duration::from_millis(260));
+    }
+
+    #[test]
+    fn notes_are_moved_around_the_latency() {
+        let grid = Grid { step: 0.25, swing: 0.0 };
+        let quantise = Quantise::new("Quantise", Some(60.0), Some(60.0), Some(grid), None);
+        for _ in 0..100 {
+            let mut data = MidiData { instruction: NOTE_ON, channel: 0, note: 60, velocity: 100 };
+            let shift = quantise.note_on(&mut data, Instant::now());
+            let latency = quantise.delay();
+            assert!(shift + Duration::from_millis(126) > latency && shift < latency + Duration::from_millis(126));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Weak;
+use log::trace;
+use may::sync::{Mutex, RwLock};
+use serde::Deserialize;
+use crate::config::MessageMatch;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterOptions {
+    // messages are routed by the first matching rule, or otherwise to `next`
+    pub(crate) rules: Vec<RouteConfig>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RouteConfig {
+    #[serde(rename = "match", default)]
+    pub(crate) matcher: MessageMatch,
+    pub(crate) to: String
+}
+
+struct Route {
+    matcher: MessageMatch,
+    to: String,
+    node: OptNode
+}
+
+pub(crate) struct Router {
+    name: String,
+    routes: Vec<Route>,
+    // the route each sounding note was sent along, which its note-off and pressure must follow
+    active: Mutex<HashMap<(u8, u8), Option<usize>>>,
+    next: OptNode
+}
+
+impl Router {
+    pub(crate) fn new(name: &str, rules: Vec<(MessageMatch, String)>) -> Self {
+        Router {
+            name: String::from(name),
+            routes: rules.into_iter()
+                .map(|(matcher, to)| Route { matcher, to, node: RwLock::new(None) })
+                .collect(),
+            active: Mutex::new(HashMap::new()),
+            next: RwLock::new(None)
+        }
+    }
+
+    fn route(&self, data: &MidiData) -> Option<usize> {
+        if data.is_note() && !data.is_note_on() {
+            let mut active = self.active.lock().unwrap();
+            let key = (data.channel, data.note);
+            let route = if data.is_note_off() { active.remove(&key) } else { active.get(&key).copied() };
+            if let Some(route) = route {
+                return route;
+            }
+        }
+        let route = self.routes.iter().position(|route| route.matcher.matches(data));
+        if data.is_note_on() {
+            self.active.lock().unwrap().insert((data.channel, data.note), route);
+        }
+        route
+    }
+}
+
+impl Node for Router {
+    fn call(&self, data: MidiData) {
+        match self.route(&data) {
+            Some(route) => {
+                trace!(target: &self.name, "Routing {:?} to {}", data, self.routes[route].to);
+                self.routes[route].node.call(data);
+            }
+            None => self.next.call(data)
+        }
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+
+    fn targets(&self) -> Vec<String> {
+        let mut targets: Vec<String> = Vec::new();
+        for route in self.routes.iter() {
+            if !targets.contains(&route.to) {
+                targets.push(route.to.clone());
+            }
+        }
+        targets
+    }
+
+    fn bind_target(&self, target: &str, node: Weak<dyn Node>) {
+        for route in self.routes.iter().filter(|route| route.to == target) {
+            route.node.bind(node.clone());
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::Weak;
+use log::trace;
+use may::sync::RwLock;
+use serde::Deserialize;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+const OCTAVE: i16 = 12;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransposeOptions {
+    #[serde(default)]
+    pub(crate) semitones: i16,
+    // the lowest and highest notes, which transposed notes are wrapped into by octaves
+    pub(crate) range: Option<(u8, u8)>
+}
+
+pub(crate) struct Transpose {
+    name: String,
+    semitones: i16,
+    // notes are shifted by octaves until they fall within the range, inclusive
+    range: Option<(u8, u8)>,
+    next: OptNode
+}
+
+impl Transpose {
+    pub(crate) fn new(name: &str, semitones: i16, range: Option<(u8, u8)>) -> Self {
+        Transpose {
+            name: String::from(name),
+            semitones,
+            range,
+            next: RwLock::new(None)
+        }
+    }
+
+    fn transpose(&self, note: u8) -> Option<u8> {
+        let mut note = note as i16 + self.semitones;
+        if let Some((low, high)) = self.range {
+            while note < low as i16 {
+                note += OCTAVE;
+            }
+            while note > high as i16 {
+                note -= OCTAVE;
+            }
+        }
+        u8::try_from(note).ok().filter(|note| *note <= 127)
+    }
+}
+
+impl Node for Transpose {
+    fn call(&self, mut data: MidiData) {
+        if !data.is_note() {
+            self.next.call(data);
+            return;
+        }
+        // notes which can't be transposed are dropped, rather than clamped onto another note
+        let Some(note) = self.transpose(data.note) else {
+            trace!(target: &self.name, "Dropped {:?}, as it can't be transposed", data);
+            return;
+        };
+        data.note = note;
+        self.next.call(data);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}
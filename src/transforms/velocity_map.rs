@@ -0,0 +1,68 @@
+use std::sync::Weak;
+use may::sync::RwLock;
+use serde::Deserialize;
+use crate::config::VelocityCurve;
+use crate::data::MidiData;
+use crate::node::{Node, OptNode};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityMapOptions {
+    pub(crate) curve: Option<VelocityCurve>,
+    #[serde(default = "default_min_velocity")]
+    pub(crate) min: u8,
+    #[serde(default = "default_max_velocity")]
+    pub(crate) max: u8,
+    // overrides both the curve and clamping
+    pub(crate) fixed: Option<u8>
+}
+
+fn default_min_velocity() -> u8 {
+    1
+}
+
+fn default_max_velocity() -> u8 {
+    127
+}
+
+// only affects note-ons, which are never mapped to a velocity of zero so as not to become note-offs
+pub(crate) struct VelocityMap {
+    curve: Option<VelocityCurve>,
+    min: u8,
+    max: u8,
+    fixed: Option<u8>,
+    next: OptNode
+}
+
+impl VelocityMap {
+    pub(crate) fn new(curve: Option<VelocityCurve>, min: u8, max: u8, fixed: Option<u8>) -> Self {
+        VelocityMap {
+            curve,
+            min: min.max(1),
+            max: max.clamp(1, 127),
+            fixed,
+            next: RwLock::new(None)
+        }
+    }
+
+    fn map(&self, velocity: u8) -> u8 {
+        if let Some(fixed) = self.fixed {
+            return fixed.clamp(1, 127);
+        }
+        let velocity = self.curve.as_ref().map(|curve| curve.apply(velocity)).unwrap_or(velocity);
+        velocity.clamp(self.min, self.max)
+    }
+}
+
+impl Node for VelocityMap {
+    fn call(&self, mut data: MidiData) {
+        if data.is_note_on() {
+            data.velocity = self.map(data.velocity);
+        }
+        self.next.call(data);
+    }
+
+    fn bind(&self, node: Weak<dyn Node>) {
+        self.next.bind(node);
+    }
+}